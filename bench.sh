@@ -0,0 +1,23 @@
+#!/bin/bash
+# Compares heuristics on a level set, writing per level stats & totals to bench_output.txt
+# usage: [TIME_LIMIT=seconds] ./bench.sh [levels.sok] [heuristic ...]
+# ex: TIME_LIMIT=60 ./bench.sh levels/microban_subset.sok hungarian corridor-conflict
+# A level only counts as solved when a solution was found before the time limit.
+
+LEVELS=${1:-levels/microban_subset.sok}
+shift
+HEURISTICS=${@:-closest-box greedy-perfect-match hungarian corridor-conflict pdb-2}
+TIME_LIMIT=${TIME_LIMIT:-300}
+OUT=bench_output.txt
+
+cargo build --release || exit 1
+
+echo "levels: $LEVELS, time limit: ${TIME_LIMIT}s" > $OUT
+for h in $HEURISTICS; do
+    echo "--- $h" >> $OUT
+    echo "level,time_elapsed,nodes_checked,solutions,pushes,moves,solution_string,push_optimal,move_optimal" >> $OUT
+    ./target/release/rustsoko -s solve "$LEVELS" --heuristic $h --time-limit $TIME_LIMIT | awk -F, -v h=$h -v out=$OUT '
+        { print NR "," $0 >> out; time += $1; nodes += $2; if ($6 != "") solved += 1 }
+        END { close(out); printf "total: %s solved %d/%d, nodes %d, time %.3fs\n", h, solved, NR, nodes, time }
+    ' | tee -a $OUT
+done
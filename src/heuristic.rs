@@ -0,0 +1,961 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::types::{Tile, Point2D, TileMatrix, CrateSet, Action, BitMatrix};
+use crate::ida_star_solver::Node;
+use crate::util;
+
+// A heuristic estimates the pushes left from a node to the goal. Heuristics are initialized once per level, so they
+// can keep state like cost matrices between calls.
+pub trait Heuristic {
+    // called with the starting level before any estimates are made.
+    fn init(&mut self, level: &TileMatrix);
+
+    // caps how long init may run, for heuristics which build tables up front. Others ignore it.
+    fn set_init_time(&mut self, _seconds: f32) {}
+
+    // usize::MAX means the heuristic knows the node can't be solved, so the search drops it.
+    fn estimate(&mut self, node: &Node) -> usize;
+
+    // estimate for a child node where only a single crate moved (old_pos -> new_pos), given the parent's h.
+    // Heuristics which can't be updated incrementally just fall back on a full estimate.
+    fn estimate_push(&mut self, _parent_h: usize, node: &Node, _old_pos: Point2D, _new_pos: Point2D) -> usize {
+        self.estimate(node)
+    }
+
+    // An admissible heuristic never overestimates, which is what makes IDA* solutions push optimal.
+    fn is_admissible(&self) -> bool;
+}
+
+pub type HeuristicMaker = fn() -> Box<dyn Heuristic>;
+
+// Maps heuristic names to constructors, so heuristics can be picked by name. (ex: --heuristic hungarian)
+// Heuristics from outside the crate can be added with register(), for that crate's own solver or generator runs. The
+// rustsoko binary only uses new(), so it never sees them.
+pub struct HeuristicRegistry {
+    entries: Vec<(&'static str, &'static str, HeuristicMaker)>,  // (name, description, constructor)
+}
+impl HeuristicRegistry {
+    // registry with only the built in heuristics.
+    pub fn new() -> HeuristicRegistry {
+        let mut registry = HeuristicRegistry { entries: Vec::new() };
+        registry.register("hungarian",
+            "The cost of a minimum perfect matching between crates & goals, using manhattan distance.",
+            || Box::new(Hungarian::default()));
+        registry.register("corridor-conflict",
+            "Hungarian matching where crates in one-wide corridors must leave through an end, & pairs of crates in a corridor which block each other only take the goals between them.",
+            || Box::new(CorridorConflict::default()));
+        registry.register("pdb-2",
+            "Pattern database with the exact push costs for groups of 2 goals, never less than hungarian.",
+            || Box::new(PatternDatabase::new(2)));
+        registry.register("pdb-3",
+            "Pattern database with the exact push costs for groups of 3 goals, never less than hungarian.",
+            || Box::new(PatternDatabase::new(3)));
+        registry.register("pdb-4",
+            "Pattern database with the exact push costs for groups of 4 goals, never less than hungarian.",
+            || Box::new(PatternDatabase::new(4)));
+        registry.register("greedy-perfect-match",
+            "Estimates a perfect match of a bipartite graph between the goals and the crates, falling back to closest-box for initially unsatisfied nodes.",
+            || Box::new(GreedyPerfectMatch::default()));
+        registry.register("closest-box",
+            "The distance from each crate to its closest goal.",
+            || Box::new(ClosestBox::default()));
+        registry.register("goal-count",
+            "The number of goals which are not covered, which is faster but also less intelligent.",
+            || Box::new(GoalCount::default()));
+        registry
+    }
+
+    // replaces any heuristic already registered under the same name.
+    pub fn register(&mut self, name: &'static str, description: &'static str, make: HeuristicMaker) {
+        self.entries.retain(|(n, _, _)| *n != name);
+        self.entries.push((name, description, make));
+    }
+
+    pub fn make(&self, name: &str) -> Option<Box<dyn Heuristic>> {
+        self.entries.iter().find(|(n, _, _)| *n == name).map(|(_, _, make)| make())
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.entries.iter().map(|(n, _, _)| *n).collect()
+    }
+
+    pub fn print(&self) {
+        for (name, description, make) in &self.entries {
+            let admissible = if make().is_admissible() { "admissible" } else { "not admissible" };
+            println!("{} ({})\n    {}", name, admissible, description);
+        }
+    }
+}
+impl Default for HeuristicRegistry {
+    fn default() -> HeuristicRegistry {
+        HeuristicRegistry::new()
+    }
+}
+
+pub fn find_goals(puzzle: &TileMatrix) -> Vec<Point2D> {
+    let mut goals: Vec<Point2D> = Vec::new();
+    for (i, tile) in puzzle.data.iter().enumerate() {
+        if matches!(tile, Tile::Goal | Tile::CrateGoal | Tile::PlayerGoal) {
+            goals.push(Point2D::new(i % puzzle.width, i / puzzle.width));
+        }
+    }
+    goals
+}
+
+// distance from every square to every goal. (square * goals.len() + goal)
+fn goal_distances(puzzle: &TileMatrix, goals: &[Point2D]) -> Vec<usize> {
+    let mut cost: Vec<usize> = Vec::with_capacity(puzzle.data.len() * goals.len());
+    for i in 0..puzzle.data.len() {
+        let pos = Point2D::new(i % puzzle.width, i / puzzle.width);
+        cost.extend(goals.iter().map(|goal_pos| util::manhattan_distance(pos, *goal_pos)));
+    }
+    cost
+}
+
+// sum the manhattan distance from the closest box to each goal.
+// This Heuristic is admissible because there is no way to push a box in fewer spaces than the manhattan distance,
+// however there are many ways to push the box in more ways. Thus h(x) <= h*(x).
+#[derive(Default)]
+pub struct ClosestBox {
+    width: usize,
+    cost: Vec<usize>,  // distance from each square to its closest goal.
+}
+impl Heuristic for ClosestBox {
+    fn init(&mut self, level: &TileMatrix) {
+        let goals = find_goals(level);
+        self.width = level.width;
+        self.cost = (0..level.data.len()).map(|i| {
+            let pos = Point2D::new(i % level.width, i / level.width);
+            goals.iter().map(|goal_pos| util::manhattan_distance(pos, *goal_pos)).min().unwrap_or(0)
+        }).collect();
+    }
+
+    fn estimate(&mut self, node: &Node) -> usize {
+        node.crates.iter().map(|crate_pos| self.cost[crate_pos.y * self.width + crate_pos.x]).sum()
+    }
+
+    // only the moved crate's term changes.
+    fn estimate_push(&mut self, parent_h: usize, _node: &Node, old_pos: Point2D, new_pos: Point2D) -> usize {
+        parent_h + self.cost[new_pos.y * self.width + new_pos.x] - self.cost[old_pos.y * self.width + old_pos.x]
+    }
+
+    fn is_admissible(&self) -> bool {
+        true
+    }
+}
+
+// damn, this heuristic sucks.
+#[derive(Default)]
+pub struct GoalCount {
+    goals: Vec<Point2D>,
+    goal_set: Option<CrateSet>,
+}
+impl Heuristic for GoalCount {
+    fn init(&mut self, level: &TileMatrix) {
+        self.goals = find_goals(level);
+        let mut goal_set = CrateSet::new(level.width, level.data.len());
+        for goal_pos in &self.goals {
+            goal_set.set(*goal_pos, true);
+        }
+        self.goal_set = Some(goal_set);
+    }
+
+    fn estimate(&mut self, node: &Node) -> usize {
+        self.goals.iter().filter(|goal_pos| !node.crates.get(**goal_pos)).count()
+    }
+
+    fn estimate_push(&mut self, parent_h: usize, _node: &Node, old_pos: Point2D, new_pos: Point2D) -> usize {
+        let goal_set = self.goal_set.as_ref().unwrap();
+        parent_h + goal_set.get(old_pos) as usize - goal_set.get(new_pos) as usize
+    }
+
+    fn is_admissible(&self) -> bool {
+        true
+    }
+}
+
+// Attempts to find perfect matches, but when it fails it simply falls back on the closest box heuristic.
+#[derive(Default)]
+pub struct GreedyPerfectMatch {
+    goal_num: usize,
+    cost: Vec<usize>,  // distance from each square to each goal.
+    dis_vec: Vec<usize>,  // scratch matrices, reused between calls.
+    dis_vec_clone: Vec<usize>,
+    taken: Vec<bool>,
+}
+impl Heuristic for GreedyPerfectMatch {
+    fn init(&mut self, level: &TileMatrix) {
+        let goals = find_goals(level);
+        self.goal_num = goals.len();
+        self.cost = goal_distances(level, &goals);
+    }
+
+    // The greedy assignment depends on the order of every crate, so a push can't be applied incrementally.
+    // Instead the rows of the cost matrix are copied straight from the cache.
+    fn estimate(&mut self, node: &Node) -> usize {
+        let width: usize = self.goal_num;
+        let dis_vec = &mut self.dis_vec;
+
+        // get initial settings. & step 0
+        dis_vec.clear();
+        for crate_pos in node.crates.iter() {
+            let row = (crate_pos.y * node.crates.width + crate_pos.x) * width;
+            dis_vec.extend_from_slice(&self.cost[row..row + width]);
+        }
+
+        self.dis_vec_clone.clear();
+        self.dis_vec_clone.extend_from_slice(dis_vec);
+        let dis_vec_clone = &self.dis_vec_clone;
+
+        // step 0
+        for y in 0..width {
+            let mut min: usize = usize::MAX;
+            for x in 0..width {
+                let dis = dis_vec[y * width + x];
+                if min > dis {
+                    min = dis;
+                }
+            }
+
+            for x in 0..width {
+                dis_vec[y * width + x] -= min;
+            }
+        }
+
+        // step 0
+        for x in 0..width {
+            let mut min: usize = usize::MAX;
+            for y in 0..width {
+                let dis = dis_vec[y * width + x];
+                if min > dis {
+                    min = dis;
+                }
+            }
+
+            for y in 0..width {
+                dis_vec[y * width + x] -= min;
+            }
+        }
+
+        // attempt assignment
+        let mut distance: usize = 0;
+        let taken = &mut self.taken;
+        taken.clear();
+        taken.resize(width, false);
+        for y in 0..width {
+            for x in 0..width {
+                if dis_vec[y * width + x] == 0 && !taken[y] {
+                    distance += dis_vec_clone[y * width + x];
+                    taken[y] = true;
+                    break;
+                }
+            }
+
+            // just take min if can't find a best.
+            if !taken[y] {
+                let mut min: usize = usize::MAX;
+                let mut min_x: usize = 0;
+                for x in 0..width {
+                    if dis_vec[y * width + x] < min {
+                        min = dis_vec[y * width + x];
+                        min_x = x;
+                    }
+                }
+                distance += dis_vec_clone[y * width + min_x];
+            }
+        }
+
+        distance
+    }
+
+    // Crates can pick the same goal here, and the column reductions then get counted more than once, so this can be
+    // larger than the cheapest matching.
+    fn is_admissible(&self) -> bool {
+        false
+    }
+}
+
+// The minimum cost perfect matching between crates & goals, found with the hungarian algorithm. Each crate needs its
+// own goal, so this is never more than the real number of pushes, but usually much closer than closest-box.
+// The matching of the last node solved is kept, so that a push only re-matches the crate which moved.
+#[derive(Default)]
+pub struct Hungarian {
+    goal_num: usize,
+    cost: Vec<usize>,  // distance from each square to each goal.
+    rows: Vec<usize>,  // start of each matched crate's row in cost.
+    matched: Option<CrateSet>,  // crates of the node matcher holds the matching for.
+    matcher: Matcher,
+    child: Matcher,  // scratch for re-matching a child, so the parent's matching is kept for its siblings.
+}
+impl Hungarian {
+    // matches every crate from scratch.
+    fn match_all(&mut self, crates: &CrateSet) -> usize {
+        let Hungarian { goal_num, cost, rows, matcher, .. } = self;
+        rows.clear();
+        rows.extend(crates.iter().map(|c| (c.y * crates.width + c.x) * *goal_num));
+        let h = matcher.solve(rows.len(), *goal_num, |crate_i, goal_i| cost[rows[crate_i] + goal_i]);
+        match &mut self.matched {
+            Some(matched) => matched.clone_from(crates),
+            None => self.matched = Some(crates.clone()),
+        }
+        h
+    }
+
+    // swaps one crate's row for another, then repairs the matching for only that row.
+    fn rematch(matcher: &mut Matcher, rows: &mut [usize], cost: &[usize], goal_num: usize, old_row: usize, new_row: usize) -> usize {
+        let row = rows.iter().position(|r| *r == old_row).unwrap();
+        rows[row] = new_row;
+        matcher.repair(row, goal_num, |crate_i, goal_i| cost[rows[crate_i] + goal_i])
+    }
+
+    // the (old, new) position of the only crate which differs between the matched node & the parent of child, if there
+    // is just one. The parent is child with the crate at new_pos moved back to old_pos.
+    fn single_move(matched: &CrateSet, child: &CrateSet, old_pos: Point2D, new_pos: Point2D) -> Option<(Point2D, Point2D)> {
+        let in_parent = |p: Point2D| p == old_pos || (p != new_pos && child.get(p));
+        let mut removed = matched.iter().filter(|c| !in_parent(*c));
+        let mut added = child.iter().map(|c| if c == new_pos { old_pos } else { c }).filter(|c| !matched.get(*c));
+        match (removed.next(), removed.next(), added.next(), added.next()) {
+            (Some(old_pos), None, Some(new_pos), None) => Some((old_pos, new_pos)),
+            _ => None,
+        }
+    }
+}
+impl Heuristic for Hungarian {
+    fn init(&mut self, level: &TileMatrix) {
+        let goals = find_goals(level);
+        self.goal_num = goals.len();
+        self.cost = goal_distances(level, &goals);
+        self.matched = None;
+    }
+
+    fn estimate(&mut self, node: &Node) -> usize {
+        self.match_all(&node.crates)
+    }
+
+    // Every child of a node is estimated before the search moves on, so the parent's matching is kept & each child
+    // re-matches a copy of it. When the parent isn't the node matched last, it is usually one push away from it (the
+    // search just went down a level, or over to a sibling), which is repaired the same way, rather than re-solved.
+    fn estimate_push(&mut self, _parent_h: usize, node: &Node, old_pos: Point2D, new_pos: Point2D) -> usize {
+        let width = node.crates.width;
+        // checked by moving the crate in the matched node, so that siblings don't need to build their parent.
+        let is_parent = match &mut self.matched {
+            Some(matched) if matched.get(old_pos) && !matched.get(new_pos) => {
+                matched.move_crate(old_pos, new_pos);
+                let same = *matched == node.crates;
+                matched.move_crate(new_pos, old_pos);
+                same
+            },
+            _ => false,
+        };
+        if !is_parent {
+            let step = self.matched.as_ref().and_then(|matched| Hungarian::single_move(matched, &node.crates, old_pos, new_pos));
+            match step {
+                Some((from, to)) if self.rows.len() == self.goal_num => {
+                    let Hungarian { goal_num, cost, rows, matched, matcher, .. } = self;
+                    Hungarian::rematch(matcher, rows, cost, *goal_num, (from.y * width + from.x) * *goal_num, 
+                                       (to.y * width + to.x) * *goal_num);
+                    matched.as_mut().unwrap().move_crate(from, to);
+                },
+                _ => {
+                    let mut parent = node.crates.clone();
+                    parent.move_crate(new_pos, old_pos);
+                    self.match_all(&parent);
+                },
+            }
+        }
+        // repairs need a crate for every goal.
+        if self.rows.len() != self.goal_num {
+            return self.match_all(&node.crates);
+        }
+
+        let Hungarian { goal_num, cost, rows, matcher, child, .. } = self;
+        let old_row = (old_pos.y * width + old_pos.x) * *goal_num;
+        let new_row = (new_pos.y * width + new_pos.x) * *goal_num;
+        child.copy_from(matcher);
+        let h = Hungarian::rematch(child, rows, cost, *goal_num, old_row, new_row);
+        let row = rows.iter().position(|r| *r == new_row).unwrap();
+        rows[row] = old_row;
+        h
+    }
+
+    fn is_admissible(&self) -> bool {
+        true
+    }
+}
+
+// Hungarian matching where crates in one-wide corridors pay for the pushes they are forced to make. A crate in a
+// corridor can only move along it, so it reaches a goal outside its corridor by leaving through one of the two ends,
+// which costs extra when the goal is beside the corridor rather than past an end. (ex: a goal in the same column as a
+// crate in a horizontal corridor costs at least 2 more pushes than manhattan distance)
+// Two crates in the same corridor also block each other. Unless the player is between them, it can never get behind
+// either one to push it out, so both are stuck in the stretch between them & are only matched to goals there. When
+// there aren't enough goals in the stretch, the node can't be solved. Every cost is still a lower bound on that
+// crate's pushes, so this stays admissible & is never below hungarian.
+#[derive(Default)]
+pub struct CorridorConflict {
+    matching: Hungarian,
+    corridors: Vec<Option<(usize, usize)>>,  // (corridor, offset along it) of each corridor square.
+    goal_corridors: Vec<Option<(usize, usize)>>,  // the same for each goal.
+    blocked: Vec<(usize, usize, usize, usize)>,  // (corridor, first offset, last offset, crates) of each stuck group.
+    squares: Vec<usize>,  // square of each crate, for the blocked matching.
+    blocked_matcher: Matcher,
+}
+impl CorridorConflict {
+    // the squares just past each end of pos's corridor, or None when that end is a wall.
+    fn corridor_exits(level: &TileMatrix, tunnels: &BitMatrix, pos: Point2D, back: Action, forward: Action) -> [Option<Point2D>; 2] {
+        let mut exits: [Option<Point2D>; 2] = [None, None];
+        for (n, action) in [back, forward].iter().enumerate() {
+            let mut end = pos.from(*action);
+            while tunnels.get(end).unwrap() {
+                end = end.from(*action);
+            }
+            if level.get(end) != Tile::Wall {
+                exits[n] = Some(end);
+            }
+        }
+        exits
+    }
+
+    // numbers each corridor & the squares along it, from left to right or top to bottom.
+    fn number_corridors(level: &TileMatrix, horizontal: &BitMatrix, vertical: &BitMatrix) -> Vec<Option<(usize, usize)>> {
+        let mut corridors: Vec<Option<(usize, usize)>> = vec![None; level.data.len()];
+        let mut count = 0;
+        for (tunnels, back, forward) in [(horizontal, Action::Left, Action::Right), (vertical, Action::Up, Action::Down)] {
+            for i in 0..level.data.len() {
+                let pos = Point2D::new(i % level.width, i / level.width);
+                // a corridor is numbered from its first square, which has no corridor square behind it.
+                if !tunnels.get(pos).unwrap() || corridors[i].is_some() || tunnels.get(pos.from(back)).unwrap() {
+                    continue;
+                }
+                let mut p = pos;
+                let mut offset = 0;
+                while tunnels.get(p).unwrap() {
+                    corridors[p.y * level.width + p.x] = Some((count, offset));
+                    p = p.from(forward);
+                    offset += 1;
+                }
+                count += 1;
+            }
+        }
+        corridors
+    }
+
+    // Finds the groups of 2 or more crates in a corridor which the player isn't between. Returns whether there are any.
+    fn find_blocked(&mut self, node: &Node) -> bool {
+        let width = node.crates.width;
+        let corridors = &self.corridors;
+        let blocked = &mut self.blocked;
+        blocked.clear();
+        for c in node.crates.iter() {
+            if let Some((corridor, offset)) = corridors[c.y * width + c.x] {
+                match blocked.iter_mut().find(|group| group.0 == corridor) {
+                    Some(group) => {
+                        group.1 = group.1.min(offset);
+                        group.2 = group.2.max(offset);
+                        group.3 += 1;
+                    },
+                    None => blocked.push((corridor, offset, offset, 1)),
+                }
+            }
+        }
+        let player = corridors[node.player.y * width + node.player.x];
+        blocked.retain(|&(corridor, first, last, crates)| {
+            crates > 1 && !matches!(player, Some((c, offset)) if c == corridor && first < offset && offset < last)
+        });
+        !blocked.is_empty()
+    }
+
+    // the matching where crates in a blocked group can only take the goals between the ends of their group.
+    fn estimate_blocked(&mut self, node: &Node) -> usize {
+        const NOT_REACHABLE: usize = 1 << 32;
+        let width = node.crates.width;
+        let CorridorConflict { matching, corridors, goal_corridors, blocked, squares, blocked_matcher } = self;
+        squares.clear();
+        squares.extend(node.crates.iter().map(|c| c.y * width + c.x));
+        let goal_num = matching.goal_num;
+        let h = blocked_matcher.solve(squares.len(), goal_num, |crate_i, goal_i| {
+            let square = squares[crate_i];
+            let group = corridors[square].and_then(|(corridor, _)| blocked.iter().find(|group| group.0 == corridor));
+            match (group, goal_corridors[goal_i]) {
+                (None, _) => matching.cost[square * goal_num + goal_i],
+                (Some(&(corridor, first, last, _)), Some((c, offset))) if c == corridor && first <= offset && offset <= last => 
+                    matching.cost[square * goal_num + goal_i],
+                _ => NOT_REACHABLE,
+            }
+        });
+        if h >= NOT_REACHABLE { usize::MAX } else { h }
+    }
+}
+impl Heuristic for CorridorConflict {
+    fn init(&mut self, level: &TileMatrix) {
+        let goals = find_goals(level);
+        let mut cost = goal_distances(level, &goals);
+        let (horizontal, vertical) = util::find_tunnels(level);
+        for i in 0..level.data.len() {
+            let pos = Point2D::new(i % level.width, i / level.width);
+            let (tunnels, back, forward) = if horizontal.get(pos).unwrap() {
+                (&horizontal, Action::Left, Action::Right)
+            } else if vertical.get(pos).unwrap() {
+                (&vertical, Action::Up, Action::Down)
+            } else {
+                continue;
+            };
+
+            let exits = CorridorConflict::corridor_exits(level, tunnels, pos, back, forward);
+            let through_exit = |goal_pos: Point2D| exits.iter().flatten()
+                .map(|e| util::manhattan_distance(pos, *e) + util::manhattan_distance(*e, goal_pos)).min();
+            for (n, goal_pos) in goals.iter().enumerate() {
+                // goals inside the corridor are reached directly.
+                let in_corridor = [back, forward].iter().any(|action| {
+                    let mut p = pos;
+                    while tunnels.get(p).unwrap() && p != *goal_pos {
+                        p = p.from(*action);
+                    }
+                    p == *goal_pos
+                });
+                if in_corridor {
+                    continue;
+                }
+
+                if let Some(c) = through_exit(*goal_pos) {
+                    cost[i * goals.len() + n] = c;
+                }
+            }
+        }
+
+        self.corridors = CorridorConflict::number_corridors(level, &horizontal, &vertical);
+        self.goal_corridors = goals.iter().map(|g| self.corridors[g.y * level.width + g.x]).collect();
+        self.matching.goal_num = goals.len();
+        self.matching.cost = cost;
+    }
+
+    fn estimate(&mut self, node: &Node) -> usize {
+        if self.find_blocked(node) {
+            return self.estimate_blocked(node);
+        }
+        self.matching.estimate(node)
+    }
+
+    // nodes without blocked crates are repaired from the last matching, & the rest are matched in full.
+    fn estimate_push(&mut self, parent_h: usize, node: &Node, old_pos: Point2D, new_pos: Point2D) -> usize {
+        if self.find_blocked(node) {
+            return self.estimate_blocked(node);
+        }
+        self.matching.estimate_push(parent_h, node, old_pos, new_pos)
+    }
+
+    fn is_admissible(&self) -> bool {
+        true
+    }
+}
+
+// Hungarian algorithm with potentials, for n rows (crates) & m >= n columns (goals). Scratch space is kept between calls.
+#[derive(Default)]
+pub struct Matcher {
+    u: Vec<i64>,
+    v: Vec<i64>,
+    p: Vec<usize>,  // row matched to each column. (1 indexed, 0 is none)
+    way: Vec<usize>,
+    minv: Vec<i64>,
+    used: Vec<bool>,
+}
+impl Matcher {
+    // returns the cost of the cheapest matching of every row to a distinct column.
+    pub fn solve<F: Fn(usize, usize) -> usize>(&mut self, n: usize, m: usize, cost: F) -> usize {
+        if n == 0 {
+            return 0;
+        }
+
+        self.u.clear();
+        self.u.resize(n + 1, 0);
+        self.v.clear();
+        self.v.resize(m + 1, 0);
+        self.p.clear();
+        self.p.resize(m + 1, 0);
+        self.way.clear();
+        self.way.resize(m + 1, 0);
+
+        for i in 1..=n {
+            self.augment(i, m, &cost);
+        }
+
+        (1..=m).filter(|&j| self.p[j] != 0).map(|j| cost(self.p[j] - 1, j - 1)).sum()
+    }
+
+    // After the last solve, one row's costs changed. Unmatching that row & lowering its potential until it is feasible
+    // again leaves every other row's matching & potentials valid, so a single augmenting path re-matches it. Only
+    // works for square problems (n == m), where the column it frees is always the one left to match.
+    pub fn repair<F: Fn(usize, usize) -> usize>(&mut self, row: usize, m: usize, cost: F) -> usize {
+        let i = row + 1;
+        if let Some(j) = (1..=m).find(|&j| self.p[j] == i) {
+            self.p[j] = 0;
+        }
+        self.u[i] = (1..=m).map(|j| cost(row, j - 1) as i64 - self.v[j]).min().unwrap_or(0);
+        self.augment(i, m, &cost);
+
+        (1..=m).filter(|&j| self.p[j] != 0).map(|j| cost(self.p[j] - 1, j - 1)).sum()
+    }
+
+    // copies another matcher's matching & potentials, reusing this one's memory.
+    pub fn copy_from(&mut self, other: &Matcher) {
+        self.u.clear();
+        self.u.extend_from_slice(&other.u);
+        self.v.clear();
+        self.v.extend_from_slice(&other.v);
+        self.p.clear();
+        self.p.extend_from_slice(&other.p);
+        self.way.clear();
+        self.way.resize(other.way.len(), 0);
+    }
+
+    // matches row i (1 indexed) by growing an alternating tree until a free column is found.
+    fn augment<F: Fn(usize, usize) -> usize>(&mut self, i: usize, m: usize, cost: &F) {
+        self.p[0] = i;
+        let mut j0 = 0;
+        self.minv.clear();
+        self.minv.resize(m + 1, i64::MAX);
+        self.used.clear();
+        self.used.resize(m + 1, false);
+
+        loop {
+            self.used[j0] = true;
+            let i0 = self.p[j0];
+            let mut delta = i64::MAX;
+            let mut j1 = 0;
+            for j in 1..=m {
+                if !self.used[j] {
+                    let cur = cost(i0 - 1, j - 1) as i64 - self.u[i0] - self.v[j];
+                    if cur < self.minv[j] {
+                        self.minv[j] = cur;
+                        self.way[j] = j0;
+                    }
+                    if self.minv[j] < delta {
+                        delta = self.minv[j];
+                        j1 = j;
+                    }
+                }
+            }
+            for j in 0..=m {
+                if self.used[j] {
+                    self.u[self.p[j]] += delta;
+                    self.v[j] -= delta;
+                } else {
+                    self.minv[j] -= delta;
+                }
+            }
+            j0 = j1;
+            if self.p[j0] == 0 {
+                break;
+            }
+        }
+
+        // flip the augmenting path.
+        while j0 != 0 {
+            let j1 = self.way[j0];
+            self.p[j0] = self.p[j1];
+            j0 = j1;
+        }
+    }
+}
+
+// Caps for building pattern databases, shared between all of a level's goal groups.
+pub const PDB_MAX_STATES: usize = 2_000_000;
+pub const PDB_BUILD_TIME: f32 = 10.0;  // in seconds
+
+// Exact push costs for one group of goals, with every other crate removed from the level.
+struct PatternTable {
+    goals: Vec<Point2D>,
+    costs: HashMap<u64, usize>,  // sorted crate squares -> fewest pushes to fill the goals (over any player position)
+    missing: usize,  // lower bound for crate squares which aren't in costs.
+}
+
+// Splits the goals into groups of up to group_size crates, then pulls crates off each group's goals to find the exact
+// push cost of every placement of that many crates. A node's estimate is the sum over groups of the cheapest way any of
+// its crates could fill the group. Crates which fill one group in a solution can't fill another, so the sum is
+// admissible. The estimate is never below the hungarian matching, since the max of admissible heuristics is taken.
+pub struct PatternDatabase {
+    pub group_size: usize,
+    pub max_states: usize,
+    pub build_time: f32,
+    tables: Vec<PatternTable>,
+    crates: Vec<usize>,  // scratch for the node's crate squares.
+    picked: Vec<usize>,  // scratch for the crates being looked up.
+    matching: Hungarian,
+}
+impl PatternDatabase {
+    pub fn new(group_size: usize) -> PatternDatabase {
+        PatternDatabase {
+            group_size, max_states: PDB_MAX_STATES, build_time: PDB_BUILD_TIME, tables: Vec::new(), 
+            crates: Vec::new(), picked: Vec::new(), matching: Hungarian::default()
+        }
+    }
+
+    // packs sorted crate squares into a key. Squares must be under 2^16, which is checked in init.
+    fn key(squares: &[usize]) -> u64 {
+        squares.iter().fold(0, |key, i| key << 16 | *i as u64)
+    }
+
+    // groups close goals together, since those are the crates which get in each other's way.
+    fn group_goals(&self, goals: &[Point2D]) -> Vec<Vec<Point2D>> {
+        let mut left: Vec<Point2D> = goals.to_vec();
+        let mut groups: Vec<Vec<Point2D>> = Vec::new();
+        while !left.is_empty() {
+            let first = left.remove(0);
+            left.sort_by_key(|g| util::manhattan_distance(first, *g));
+            let mut group = vec![first];
+            let rest = left.split_off((self.group_size - 1).min(left.len()));
+            group.append(&mut left);
+            left = rest;
+            groups.push(group);
+        }
+        groups
+    }
+
+    // breadth first search of pulls, starting from the group's goals filled with the player anywhere it could be.
+    // Returns the table & how many states were searched.
+    fn build_table(floor: &TileMatrix, inside: &[usize], goals: Vec<Point2D>, max_states: usize, 
+                   deadline: Instant) -> (PatternTable, usize) {
+        let width = floor.width;
+        let mut map = floor.clone();
+        let mut crate_set = CrateSet::new(width, floor.data.len());
+        let mut reach = util::Reachability::new(floor.data.len());
+        let mut child_reach = util::Reachability::new(floor.data.len());
+
+        let mut squares: Vec<usize> = goals.iter().map(|g| g.y * width + g.x).collect();
+        squares.sort_unstable();
+        let mut costs: HashMap<u64, usize> = HashMap::new();
+        costs.insert(PatternDatabase::key(&squares), 0);
+
+        // one start state for each area the player could be in.
+        let mut visited: HashSet<(u64, usize)> = HashSet::new();
+        let mut layer: Vec<(Vec<usize>, Point2D)> = Vec::new();
+        for goal_pos in &goals {
+            crate_set.set(*goal_pos, true);
+        }
+        for &i in inside {
+            let player = Point2D::new(i % width, i / width);
+            if crate_set.get(player) {
+                continue;
+            }
+            reach.fill(floor, &crate_set, player);
+            if visited.insert((PatternDatabase::key(&squares), reach.player_norm)) {
+                layer.push((squares.clone(), player));
+            }
+        }
+        for goal_pos in &goals {
+            crate_set.set(*goal_pos, false);
+        }
+
+        let mut depth = 0;
+        let mut complete = true;
+        'search: while !layer.is_empty() {
+            let mut next_layer: Vec<(Vec<usize>, Point2D)> = Vec::new();
+            for (squares, player) in &layer {
+                if visited.len() >= max_states || Instant::now() > deadline {
+                    complete = false;
+                    break 'search;
+                }
+
+                for &i in squares {
+                    crate_set.set(Point2D::new(i % width, i / width), true);
+                    map.data[i] = Tile::Crate;
+                }
+                reach.fill(floor, &crate_set, *player);
+
+                for (n, &i) in squares.iter().enumerate() {
+                    let crate_pos = Point2D::new(i % width, i / width);
+                    for action in [Action::Left, Action::Right, Action::Up, Action::Down] {
+                        if !reach.is_reachable(crate_pos.from(action), width) || !util::can_pull(&map, crate_pos, action) {
+                            continue;
+                        }
+
+                        let crate_end = crate_pos.from(action);
+                        let mut child = squares.clone();
+                        child[n] = crate_end.y * width + crate_end.x;
+                        child.sort_unstable();
+
+                        crate_set.move_crate(crate_pos, crate_end);
+                        child_reach.fill(floor, &crate_set, crate_end.from(action));
+                        crate_set.move_crate(crate_end, crate_pos);
+
+                        let key = PatternDatabase::key(&child);
+                        if visited.insert((key, child_reach.player_norm)) {
+                            costs.entry(key).or_insert(depth + 1);
+                            next_layer.push((child, crate_end.from(action)));
+                        }
+                    }
+                }
+
+                for &i in squares {
+                    crate_set.set(Point2D::new(i % width, i / width), false);
+                    map.data[i] = Tile::Floor;
+                }
+            }
+            layer = next_layer;
+            depth += 1;
+        }
+
+        // An unfinished search has found every placement up to depth pushes. A finished one has found every placement
+        // which can fill the goals at all, so anything missing is a deadlock.
+        let missing = if complete { depth } else { depth + 1 };
+        (PatternTable { goals, costs, missing }, visited.len())
+    }
+
+    // cheapest way for any group_size crates to fill the table's goals. (crates are sorted)
+    fn lookup(table: &PatternTable, crates: &[usize], picked: &mut Vec<usize>, start: usize) -> usize {
+        if picked.len() == table.goals.len() {
+            return *table.costs.get(&PatternDatabase::key(picked)).unwrap_or(&table.missing);
+        }
+
+        let mut min = table.missing;
+        let need = table.goals.len() - picked.len();
+        for i in start..=crates.len().saturating_sub(need) {
+            picked.push(crates[i]);
+            min = min.min(PatternDatabase::lookup(table, crates, picked, i + 1));
+            picked.pop();
+            if min == 0 {
+                break;
+            }
+        }
+        min
+    }
+}
+impl Heuristic for PatternDatabase {
+    fn set_init_time(&mut self, seconds: f32) {
+        self.build_time = self.build_time.min(seconds);
+    }
+
+    fn init(&mut self, level: &TileMatrix) {
+        self.matching.init(level);
+        self.tables.clear();
+        if level.data.len() >= 1 << 16 {
+            return;  // too large to pack into keys, so only the matching is used.
+        }
+
+        // only squares the player can get to matter.
+        let floor = util::floor_map(level);
+        let player = level.data.iter().position(|t| matches!(t, Tile::Player | Tile::PlayerGoal));
+        let inside: Vec<usize> = match player {
+            Some(i) => {
+                let mut reach = util::Reachability::new(level.data.len());
+                reach.fill(&floor, &CrateSet::new(level.width, level.data.len()), Point2D::new(i % level.width, i / level.width));
+                (0..level.data.len()).filter(|j| reach.is_reachable(Point2D::new(j % level.width, j / level.width), level.width)).collect()
+            },
+            None => return,
+        };
+
+        let timer = Instant::now();
+        let mut states_used: usize = 0;
+        let groups = self.group_goals(&find_goals(level));
+        let group_num = groups.len();
+        for (n, goals) in groups.into_iter().enumerate() {
+            // split whatever budget is left evenly between the remaining groups.
+            let left = group_num - n;
+            let time_left = (self.build_time - timer.elapsed().as_secs_f32()).max(0.0) / left as f32;
+            let states_left = self.max_states.saturating_sub(states_used) / left;
+            let deadline = Instant::now() + Duration::from_secs_f32(time_left);
+            let (table, states) = PatternDatabase::build_table(&floor, &inside, goals, states_left, deadline);
+            states_used += states;
+            self.tables.push(table);
+        }
+    }
+
+    fn estimate(&mut self, node: &Node) -> usize {
+        let PatternDatabase { tables, crates, picked, matching, .. } = self;
+        let width = node.crates.width;
+        crates.clear();
+        crates.extend(node.crates.iter().map(|c| c.y * width + c.x));
+
+        let sum = tables.iter().map(|table| PatternDatabase::lookup(table, crates, picked, 0)).sum();
+        matching.estimate(node).max(sum)
+    }
+
+    fn is_admissible(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    const LEVEL: &str = "\
+##########
+#   #    #
+# $$#  . #
+# $   #. #
+##  #  . #
+#  $  ## #
+# @  #  .#
+##########
+";
+
+    // moves random crates to random floor squares, checking each repaired estimate against a full one.
+    fn check_pushes(mut incremental: Box<dyn Heuristic>, mut full: Box<dyn Heuristic>) {
+        let level = TileMatrix::from_string(LEVEL);
+        incremental.init(&level);
+        full.init(&level);
+        let floor: Vec<Point2D> = (0..level.data.len())
+            .map(|i| Point2D::new(i % level.width, i / level.width))
+            .filter(|p| level.get(*p) != Tile::Wall)
+            .collect();
+        let mut crates = CrateSet::new(level.width, level.data.len());
+        for (i, tile) in level.data.iter().enumerate() {
+            if matches!(tile, Tile::Crate | Tile::CrateGoal) {
+                crates.set(Point2D::new(i % level.width, i / level.width), true);
+            }
+        }
+
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut parent = Node::default(crates, floor[0]);
+        parent.h = incremental.estimate(&parent);
+        for _ in 0..2000 {
+            let old_pos = parent.crates.iter().nth(rng.gen_range(0, 4)).unwrap();
+            let new_pos = floor[rng.gen_range(0, floor.len())];
+            if parent.crates.get(new_pos) {
+                continue;
+            }
+            let mut crates = parent.crates.clone();
+            crates.move_crate(old_pos, new_pos);
+            let mut child = Node::make_new(Action::NoMove, 1, crates, old_pos, parent.g + 1);
+            child.h = incremental.estimate_push(parent.h, &child, old_pos, new_pos);
+            assert_eq!(child.h, full.estimate(&child));
+
+            // most of the time, try a sibling instead of going down to the child.
+            if rng.gen_range(0, 3) == 0 {
+                parent = child;
+            }
+        }
+    }
+
+    #[test]
+    fn hungarian_pushes_match_full_estimate() {
+        check_pushes(Box::new(Hungarian::default()), Box::new(Hungarian::default()));
+    }
+
+    #[test]
+    fn corridor_conflict_pushes_match_full_estimate() {
+        check_pushes(Box::new(CorridorConflict::default()), Box::new(CorridorConflict::default()));
+    }
+
+    fn corridor_estimate(level: &str) -> usize {
+        let level = TileMatrix::from_string(level);
+        let mut heuristic = CorridorConflict::default();
+        heuristic.init(&level);
+        let (crates, player) = util::replay_pushes(&level, &[]).unwrap()[0].clone();
+        heuristic.estimate(&Node::default(crates, player))
+    }
+
+    #[test]
+    fn corridor_crates_block_each_other() {
+        // the player can't get behind either crate, so neither can leave the corridor for the goals.
+        assert_eq!(corridor_estimate("##########\n#@ $ $  .#\n####### .#\n##########\n"), usize::MAX);
+        // from between the crates, the player can push the right one out.
+        assert_ne!(corridor_estimate("##########\n#  $@$  .#\n####### .#\n##########\n"), usize::MAX);
+        // the corridor crates have to take the goals between them, leaving the goal past the end to the crate below.
+        assert_eq!(corridor_estimate("##########\n#@$.. $. #\n####### ##\n#   $   ##\n#       ##\n##########\n"), 8);
+    }
+}
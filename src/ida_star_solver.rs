@@ -1,168 +1,82 @@
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::cmp::Ordering;
 
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use crate::types::{Tile, Point2D, TileMatrix, Action, RunDat, BitMatrix};
+use crate::types::{Tile, Point2D, TileMatrix, Action, RunDat, BitMatrix, CrateSet, GoalRoom};
 use crate::util;
+use crate::heuristic::Heuristic;
 
-const TIME_LIMIT: u64 = 300;
+pub const TIME_LIMIT: u64 = 300;  // default seconds before a search gives up.
 const PER_NODE_TIME_CHECK: usize = 10_000;  // check time once per n nodes
 
-pub mod heuristic {
-    use super::*;
-
-    // sum the manhattan distance from the closest box to each goal.
-    // This Heuristic is admissible because there is no way to push a box in fewer spaces than the manhattan distance,
-    // however there are many ways to push the box in more ways. Thus h(x) <= h*(x).
-    pub fn closest_box (solver: &IDAStarSolver, node: &Node) -> usize {
-        let mut distance: usize = 0;
-        for crate_pos in &node.crates {
-            let mut min: usize = std::usize::MAX;
-            for goal_pos in &solver.goals {
-                let dis = util::manhattan_distance(*crate_pos, *goal_pos);
-                if min > dis {
-                    min = dis;
-                }
-            }
-            distance += min;
-        }
-        distance
-    }
-
-    // damn, this heuristic sucks.
-    pub fn goal_count (_solver: &IDAStarSolver, node: &Node) -> usize {
-        let mut goals: usize = 0;
-        for tile in &node.map.data {
-            goals += match tile {
-                Tile::Goal => 1,
-                Tile::PlayerGoal => 1,
-                _ => 0,
-            };
-        }
-        goals
-    }
-
-    // Attempts to find perfect matches, but when it fails it simply falls back on the closest box heuristic.
-    pub fn greedy_perfect_match(solver: &IDAStarSolver, node: &Node) -> usize {
-        let mut dis_vec: Vec<usize> = Vec::new();
-        let width: usize = node.crates.len();
-
-        // get initial settings. & step 0
-        for crate_pos in &node.crates {
-            for goal_pos in &solver.goals {
-                let dis = util::manhattan_distance(*crate_pos, *goal_pos);
-                dis_vec.push(dis);
-            }
-        }
-
-        let dis_vec_clone = dis_vec.clone();
-
-        // step 0
-        for y in 0..width {
-            let mut min: usize = std::usize::MAX;
-            for x in 0..width {
-                let dis = dis_vec[y * width + x];
-                if min > dis {
-                    min = dis;
-                }
-            }
-
-            for x in 0..width {
-                dis_vec[y * width + x] -= min;
-            }
-        }
-
-        // step 0
-        for x in 0..width {
-            let mut min: usize = std::usize::MAX;
-            for y in 0..width {
-                let dis = dis_vec[y * width + x];
-                if min > dis {
-                    min = dis;
-                }
-            }
-
-            for y in 0..width {
-                dis_vec[y * width + x] -= min;
-            }
-        }
-
-        // attempt assignment
-        let mut distance: usize = 0;
-        let mut taken: Vec<bool> = Vec::new();
-        taken.resize(width, false);
-        for y in 0..width {
-            for x in 0..width {
-                if dis_vec[y * width + x] == 0 && !taken[y] {
-                    distance += dis_vec_clone[y * width + x];
-                    taken[y] = true;
-                    break;
-                }
-            }
-
-            // just take min if can't find a best.
-            if taken[y] == false {
-                let mut min: usize = std::usize::MAX;
-                let mut min_x: usize = 0;
-                for x in 0..width {
-                    if dis_vec[y * width + x] < min {
-                        min = dis_vec[y * width + x];
-                        min_x = x;
-                    }
-                }
-                distance += dis_vec_clone[y * width + min_x];
-            }
-        }
-
-        return distance;
-    }
-}
-
 #[derive(Clone)]
 pub struct Node {
     pub action: Action,
-    pub map: TileMatrix,
-    pub crates: Vec<Point2D>,
-    pub player: Point2D,
+    pub crates: CrateSet,
+    pub player: Point2D,  // where the player ended up after the push.
+    pub player_norm: usize,  // smallest index the player can walk to. (set when the node is expanded)
+    pub pushes: usize,  // how many times action is repeated. (more than 1 for tunnel macros)
+    pub goal_macro: Option<usize>,  // index of the goal room macro used to reach this node.
     pub g: usize,  // this is number of pushes
     pub h: usize,  // for storing heuristic(node)
     pub hash: u64,  // odd but okay
 }
 impl Node {
     // make root
-    pub fn default(map: TileMatrix, crates: Vec<Point2D>, player: Point2D) -> Node {
-        let hash = Node::hash(&crates, &player);
+    pub fn default(crates: CrateSet, player: Point2D) -> Node {
         Node {
-            action: Action::NoMove, map, crates, player, g: 0, h: 0, hash
+            action: Action::NoMove, crates, player, player_norm: 0, pushes: 0, goal_macro: None, g: 0, h: 0, hash: 0
         }
     }
 
-    pub fn make_new(action: Action, map: TileMatrix, 
-                crates: Vec<Point2D>, player: Point2D, g: usize) -> Node {
-        let hash = Node::hash(&crates, &player);
+    pub fn make_new(action: Action, pushes: usize, crates: CrateSet, player: Point2D, g: usize) -> Node {
         Node {
-            action, map, crates, player, g, h: 0, hash 
+            action, crates, player, player_norm: 0, pushes, goal_macro: None, g, h: 0, hash: 0
         }
     }
 
-    // hashes crate positions & player position.
-    fn hash(crates: &Vec<Point2D>, player: &Point2D) -> u64 {
+    // Positions where the player has only walked around are the same state, so the player is normalized to the
+    // smallest index in its reachable area.
+    pub fn normalize(&mut self, player_norm: usize) {
+        self.player_norm = player_norm;
         let mut s = DefaultHasher::new();
-        crates.hash(&mut s);
-        player.hash(&mut s);
-        s.finish()
+        self.crates.hash(&mut s);
+        player_norm.hash(&mut s);
+        self.hash = s.finish();
+    }
+
+    pub fn is_same_state(&self, other: &Node) -> bool {
+        self.hash == other.hash && self.player_norm == other.player_norm && self.crates == other.crates
+    }
+
+    // What is on the given square, with static_map holding only walls, goals & floor.
+    pub fn tile(&self, static_map: &TileMatrix, p: Point2D) -> Tile {
+        match (static_map.get(p), self.crates.get(p)) {
+            (Tile::Goal, true) => Tile::CrateGoal,
+            (_, true) => Tile::Crate,
+            (tile, false) => tile,
+        }
+    }
+
+    // Builds the full map of this node, which is only needed for pathfinding.
+    pub fn make_map(&self, static_map: &TileMatrix) -> TileMatrix {
+        let mut map = static_map.clone();
+        for crate_pos in self.crates.iter() {
+            map.set(crate_pos, self.tile(static_map, crate_pos));
+        }
+        map
     }
 
     // Simple freezed deadlock detection, just to see how much it helps.
-    pub fn is_deadlocked(&self, moved_crate: Point2D) -> bool {
-        match self.map.get(moved_crate) {
+    pub fn is_deadlocked(&self, static_map: &TileMatrix, moved_crate: Point2D) -> bool {
+        match self.tile(static_map, moved_crate) {
             Tile::CrateGoal => {
                 let _width = 3;
-                let sur_map: Vec<Tile> = vec![
+                let sur_map: Vec<Tile> = [
                     moved_crate.from(Action::Up).from(Action::Left),
                     moved_crate.from(Action::Up),
                     moved_crate.from(Action::Up).from(Action::Right),
@@ -172,26 +86,23 @@ impl Node {
                     moved_crate.from(Action::Down).from(Action::Left),
                     moved_crate.from(Action::Down),
                     moved_crate.from(Action::Down).from(Action::Right)
-                ].iter().map(|p| self.map.get(*p)).collect();  // surround map
-
-                if sur_map[0].is_freezable() && sur_map[1].is_freezable() && sur_map[3].is_freezable() && 
-                   (sur_map[0].is_pure_crate() || sur_map[1].is_pure_crate() || sur_map[3].is_pure_crate()) {
-                    return true;
-                } else if sur_map[1].is_freezable() && sur_map[2].is_freezable() && sur_map[5].is_freezable() && 
-                          (sur_map[1].is_pure_crate() || sur_map[2].is_pure_crate() || sur_map[5].is_pure_crate()) {
-                    return true;
-                } else if sur_map[3].is_freezable() && sur_map[6].is_freezable() && sur_map[7].is_freezable() && 
-                          (sur_map[3].is_pure_crate() || sur_map[6].is_pure_crate() || sur_map[7].is_pure_crate()) {
-                    return true;
-                } else if sur_map[5].is_freezable() && sur_map[7].is_freezable() && sur_map[8].is_freezable() && 
-                          (sur_map[5].is_pure_crate() || sur_map[7].is_pure_crate() || sur_map[8].is_pure_crate()) {
+                ].iter().map(|p| self.tile(static_map, *p)).collect();  // surround map
+
+                if (sur_map[0].is_freezable() && sur_map[1].is_freezable() && sur_map[3].is_freezable() && 
+                    (sur_map[0].is_pure_crate() || sur_map[1].is_pure_crate() || sur_map[3].is_pure_crate())) ||
+                   (sur_map[1].is_freezable() && sur_map[2].is_freezable() && sur_map[5].is_freezable() && 
+                    (sur_map[1].is_pure_crate() || sur_map[2].is_pure_crate() || sur_map[5].is_pure_crate())) ||
+                   (sur_map[3].is_freezable() && sur_map[6].is_freezable() && sur_map[7].is_freezable() && 
+                    (sur_map[3].is_pure_crate() || sur_map[6].is_pure_crate() || sur_map[7].is_pure_crate())) ||
+                   (sur_map[5].is_freezable() && sur_map[7].is_freezable() && sur_map[8].is_freezable() && 
+                    (sur_map[5].is_pure_crate() || sur_map[7].is_pure_crate() || sur_map[8].is_pure_crate())) {
                     return true;
                 }
                 //return false;
             },
             Tile::Crate => {
                 let _width = 3;
-                let sur_map: Vec<Tile> = vec![
+                let sur_map: Vec<Tile> = [
                     moved_crate.from(Action::Up).from(Action::Left),
                     moved_crate.from(Action::Up),
                     moved_crate.from(Action::Up).from(Action::Right),
@@ -201,108 +112,309 @@ impl Node {
                     moved_crate.from(Action::Down).from(Action::Left),
                     moved_crate.from(Action::Down),
                     moved_crate.from(Action::Down).from(Action::Right)
-                ].iter().map(|p| self.map.get(*p)).collect();  // surround map
+                ].iter().map(|p| self.tile(static_map, *p)).collect();  // surround map
 
-                if sur_map[1] == Tile::Wall && sur_map[3] == Tile::Wall {
-                    return true;
-                } else if sur_map[1] == Tile::Wall && sur_map[5] == Tile::Wall {
-                    return true;
-                } else if sur_map[3] == Tile::Wall && sur_map[7] == Tile::Wall {
-                    return true;
-                } else if sur_map[5] == Tile::Wall && sur_map[7] == Tile::Wall {
+                // wall corners.
+                if (sur_map[1] == Tile::Wall || sur_map[7] == Tile::Wall) && 
+                   (sur_map[3] == Tile::Wall || sur_map[5] == Tile::Wall) {
                     return true;
                 }
 
-                if sur_map[0].is_freezable() && sur_map[1].is_freezable() && sur_map[3].is_freezable() {
-                    return true;
-                } else if sur_map[1].is_freezable() && sur_map[2].is_freezable() && sur_map[5].is_freezable() {
-                    return true;
-                } else if sur_map[3].is_freezable() && sur_map[6].is_freezable() && sur_map[7].is_freezable() {
-                    return true;
-                } else if sur_map[5].is_freezable() && sur_map[7].is_freezable() && sur_map[8].is_freezable() {
+                if (sur_map[0].is_freezable() && sur_map[1].is_freezable() && sur_map[3].is_freezable()) ||
+                   (sur_map[1].is_freezable() && sur_map[2].is_freezable() && sur_map[5].is_freezable()) ||
+                   (sur_map[3].is_freezable() && sur_map[6].is_freezable() && sur_map[7].is_freezable()) ||
+                   (sur_map[5].is_freezable() && sur_map[7].is_freezable() && sur_map[8].is_freezable()) {
                     return true;
                 }
             }
             _ => (),
         };
+        self.is_closed_diagonal(static_map, moved_crate)
+    }
+
+    // Closed diagonal deadlock detection. Looks for an empty square next to the moved crate which is enclosed by a
+    // diamond of crates & walls, like so:
+    //    #$
+    //    $ $
+    //     $#
+    // If every crate in the diamond has a wall on one of its diagonal corners, then none of them can be pushed
+    // sideways, and the player can never stand in the middle to push them outwards. At most one crate can ever
+    // be pushed into the middle square, after which everything is frozen.
+    fn is_closed_diagonal(&self, static_map: &TileMatrix, moved_crate: Point2D) -> bool {
+        let directions = [Action::Up, Action::Down, Action::Left, Action::Right];
+        for dir in &directions {
+            let middle = moved_crate.from(*dir);
+            match self.tile(static_map, middle) {
+                _ if middle == self.player => continue,  // the player can't already be inside.
+                Tile::Floor => (),
+                Tile::Goal => (),
+                _ => continue,  // must be empty.
+            }
+
+            let mut is_closed = true;
+            let mut loose_crates = 0;  // crates in the diamond which are not on a goal.
+            for side in &directions {
+                let ring_pos = middle.from(*side);
+                let (corner_a, corner_b) = match side {
+                    Action::Up | Action::Down => (ring_pos.from(Action::Left), ring_pos.from(Action::Right)),
+                    _ => (ring_pos.from(Action::Up), ring_pos.from(Action::Down)),
+                };
+                match self.tile(static_map, ring_pos) {
+                    Tile::Wall => (),
+                    Tile::Crate | Tile::CrateGoal => {
+                        if self.tile(static_map, corner_a) != Tile::Wall && self.tile(static_map, corner_b) != Tile::Wall {
+                            is_closed = false;
+                            break;
+                        } else if self.tile(static_map, ring_pos) == Tile::Crate {
+                            loose_crates += 1;
+                        }
+                    },
+                    _ => {
+                        is_closed = false;
+                        break;
+                    },
+                }
+            }
+
+            // a single loose crate is fine if it can still be pushed into the middle goal.
+            if is_closed && (loose_crates >= 2 || (loose_crates == 1 && self.tile(static_map, middle) != Tile::Goal)) {
+                return true;
+            }
+        }
         false
     }
 }
 
+// called with each solution's nodes & actions. Returning false stops the search.
+pub type SolutionCallback = Box<dyn FnMut(&[Node], &[Action]) -> bool>;
+
+// How a search ended.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SearchEnd {
+    Solved,
+    Unsolvable,
+    TimeLimit,
+    StateLimit,
+}
+impl SearchEnd {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            SearchEnd::Solved => "solved",
+            SearchEnd::Unsolvable => "unsolvable",
+            SearchEnd::TimeLimit => "time_limit",
+            SearchEnd::StateLimit => "state_limit",
+        }
+    }
+}
+
+// Distinct push optimal solutions, keyed by the pushes made on each crate. Solutions which only differ in walking, or 
+// in the order of pushes on different crates, count as one, & the fewest moves version of each is kept.
+#[derive(Clone)]
+pub struct DistinctSolutions {
+    pub solutions: Vec<Vec<Action>>,
+    pub capped: bool,  // set when more than max distinct solutions were found, so some aren't listed.
+    level: TileMatrix,  // the starting state, which push paths are found from.
+    max: usize,
+    index: HashMap<Vec<Vec<Action>>, usize>,  // push paths -> solution.
+}
+impl DistinctSolutions {
+    fn new(level: TileMatrix, max: usize) -> DistinctSolutions {
+        DistinctSolutions { solutions: Vec::new(), capped: false, level, max, index: HashMap::new() }
+    }
+
+    // keeps actions if it is a new push path & there is room, or if it walks less than the kept version of its path.
+    fn add(&mut self, actions: &[Action]) {
+        let key = util::crate_push_paths(&self.level, actions);
+        if let Some(&i) = self.index.get(&key) {
+            if actions.len() < self.solutions[i].len() {
+                self.solutions[i] = actions.to_vec();
+            }
+        } else if self.solutions.len() >= self.max {
+            self.capped = true;
+        } else {
+            self.index.insert(key, self.solutions.len());
+            self.solutions.push(actions.to_vec());
+        }
+    }
+
+    // like add, but the solver's best solution is always listed, in place of the last entry when the list is full.
+    fn add_best(&mut self, actions: &[Action]) {
+        let key = util::crate_push_paths(&self.level, actions);
+        if !self.index.contains_key(&key) && self.solutions.len() >= self.max && self.max > 0 {
+            let last = self.solutions.pop().unwrap();
+            self.index.remove(&util::crate_push_paths(&self.level, &last));
+            self.capped = true;
+        }
+        self.add(actions);
+    }
+}
+
 // Desc:
 //   This solver works in terms of pushes, finding best moves after execution.
 pub struct IDAStarSolver {
     debug: bool,
     deadlock_hashing_on: bool,
     rundat: RunDat,
-    goals: Vec<Point2D>,
+    static_map: TileMatrix,  // only walls, goals & floor. crates live in each node.
+    goal_set: CrateSet,  // a node is solved when its crates match this.
+    target_player: Option<Point2D>,  // when set, the player must also be able to walk here at the goal.
+    max_bound: usize,  // the search gives up once the bound goes past this.
     path: Vec<Node>,  // current search path (acts like a stack)
-    heuristic: fn(&IDAStarSolver, &Node) -> usize,  // estimated cost of the cheapest path (node..goal)
-    solutions: Vec<Vec<Node>>,
+    heuristic: Box<dyn Heuristic>,  // estimated cost of the cheapest path (node..goal)
+    solutions: usize,  // how many solutions have been found at the final bound.
+    best_solution: Vec<Action>,  // fewest moves out of the solutions found so far.
+    on_solution: Option<SolutionCallback>,  // called with every solution & its actions.
+    distinct: Option<DistinctSolutions>,  // only collected when asked for.
+    walks: util::PathCache,  // walks between pushes, shared between solutions.
     simple_deadlocks: BitMatrix,
-    deadlocks: HashSet<TileMatrix>,
+    horizontal_tunnels: BitMatrix,
+    vertical_tunnels: BitMatrix,
+    goal_room: Option<GoalRoom>,  // only used when goal room macros are on.
+    deadlocks: HashSet<(CrateSet, usize)>,  // (crates, normalized player)
+    reach: util::Reachability,  // scratch space for expanding the end node of the path.
     timer: Instant,
+    time_limit: Duration,
     search_over: bool,
+    stopped: bool,  // set when the solution callback asks for the search to stop.
 }
 impl IDAStarSolver {
-    pub fn new(puzzle: TileMatrix, heuristic: fn(&IDAStarSolver, &Node) -> usize, deadlock_hashing_on: bool, debug: bool) -> IDAStarSolver {
-        // remove static pieces from the puzzle.
+    pub fn new(puzzle: TileMatrix, mut heuristic: Box<dyn Heuristic>, deadlock_hashing_on: bool, 
+               goal_room_macros: bool, debug: bool) -> IDAStarSolver {
+        // remove dynamic pieces from the puzzle.
+        let mut static_map = puzzle.clone();
         let mut goals: Vec<Point2D> = Vec::new();
-        let mut crates: Vec<Point2D> = Vec::new();
+        let mut goal_set = CrateSet::new(puzzle.width, puzzle.data.len());
+        let mut crates = CrateSet::new(puzzle.width, puzzle.data.len());
         let mut player: Option<Point2D> = None;
         for (i, tile) in puzzle.data.iter().enumerate() {
-            let (x, y) = (i % puzzle.width, i / puzzle.width);
+            let pos = Point2D::new(i % puzzle.width, i / puzzle.width);
             match tile {
                 Tile::Player => {
-                    player = Some(Point2D::new(x, y));
+                    player = Some(pos);
+                    static_map.set(pos, Tile::Floor);
                 },
                 Tile::PlayerGoal => {
-                    player = Some(Point2D::new(x, y));
-                    goals.push( Point2D::new(x, y) );
+                    player = Some(pos);
+                    goals.push(pos);
+                    static_map.set(pos, Tile::Goal);
                 },
                 Tile::Crate => {
-                    crates.push( Point2D::new(x, y) );
+                    crates.set(pos, true);
+                    static_map.set(pos, Tile::Floor);
                 },
                 Tile::CrateGoal => {
-                    goals.push( Point2D::new(x, y) );
-                    crates.push( Point2D::new(x, y) );
+                    goals.push(pos);
+                    crates.set(pos, true);
+                    static_map.set(pos, Tile::Goal);
                 },
                 Tile::Goal => {
-                    goals.push( Point2D::new(x, y) );
+                    goals.push(pos);
                 },
                 _ => (),
             };
         }
+        for goal_pos in &goals {
+            goal_set.set(*goal_pos, true);
+        }
 
+        heuristic.init(&puzzle);
         let simple_deadlocks: BitMatrix = util::find_simple_deadlocks(&puzzle, &goals);
+        let (horizontal_tunnels, vertical_tunnels) = util::find_tunnels(&puzzle);
+
+        let mut goal_room: Option<GoalRoom> = None;
+        if goal_room_macros {
+            goal_room = util::find_goal_room(&puzzle, &goals);
+            if debug {
+                match &goal_room {
+                    Some(room) => println!("DEBUG: found goal room with entrance ({}, {})", room.entrance.x, room.entrance.y),
+                    None => println!("DEBUG: no goal room found"),
+                }
+            }
+        }
 
         // puzzle size is a good vector size estimate which should increase performance because IDA* doesn't particularly
         // need lots of memory. -------------------------------> vVVVv
         let mut path: Vec<Node> = Vec::with_capacity(puzzle.data.len());
-        let root_node = Node::default(puzzle, crates, player.unwrap());
+        let root_node = Node::default(crates, player.unwrap());
         path.push(root_node);
 
         let mut solver = IDAStarSolver {
-            debug, deadlock_hashing_on, rundat: RunDat::new(), goals, path, 
-            heuristic, solutions: Vec::new(), deadlocks: HashSet::new(), simple_deadlocks,
-            timer: Instant::now(), search_over: false
+            debug, deadlock_hashing_on, rundat: RunDat::new(), static_map, goal_set, target_player: None, 
+            max_bound: usize::MAX, path, 
+            heuristic, solutions: 0, best_solution: Vec::new(), on_solution: None, distinct: None, 
+            walks: util::PathCache::new(puzzle.data.len()), deadlocks: HashSet::new(), simple_deadlocks,
+            horizontal_tunnels, vertical_tunnels, goal_room, reach: util::Reachability::new(puzzle.data.len()), 
+            timer: Instant::now(), time_limit: Duration::from_secs(TIME_LIMIT), search_over: false, stopped: false
         };
-        solver.path[0].h = (solver.heuristic)(&solver, &solver.path[0]); 
+        solver.path[0].h = solver.heuristic.estimate(&solver.path[0]);
         solver
     }
 
     fn is_simple_deadlock(&self, pos: Point2D) -> bool {
         !self.simple_deadlocks.get(pos).unwrap()
     }
+
+    // crates can be pushed onto anything but walls & other crates.
+    fn is_free(&self, crates: &CrateSet, pos: Point2D) -> bool {
+        self.static_map.get(pos) != Tile::Wall && !crates.get(pos)
+    }
+
+    // A crate which has been pushed into a tunnel, with the player following it in, can only ever be pushed onwards.
+    // Crates sitting on goals are left alone, since the solution may want them to stay there.
+    fn is_tunnel_push(&self, crates: &CrateSet, action: Action, player: Point2D, crate_pos: Point2D) -> bool {
+        let tunnels = match action {
+            Action::PushLeft | Action::PushRight => &self.horizontal_tunnels,
+            _ => &self.vertical_tunnels,
+        };
+        if !tunnels.get(player).unwrap() || !tunnels.get(crate_pos).unwrap() || self.goal_set.get(crate_pos) {
+            return false;
+        }
+
+        let next = crate_pos.from(action);
+        self.is_free(crates, next) && !self.is_simple_deadlock(next)
+    }
     
-    // if a crate is not on a goal, then it is not solved.
-    fn is_goal(&self, node: &Node) -> bool {
-        for tile in &node.map.data {
-            if let Tile::Crate = tile {
-                return false;
-            }
+    // every crate must be on a goal, & the player must be able to reach the target if there is one.
+    fn is_goal(&mut self) -> bool {
+        let node: &Node = self.path.last().unwrap();
+        if node.crates != self.goal_set {
+            return false;
         }
-        true
+        match self.target_player {
+            Some(target) => {
+                self.reach.fill(&self.static_map, &node.crates, node.player);
+                self.reach.is_reachable(target, self.static_map.width)
+            },
+            None => true,
+        }
+    }
+
+    // Makes the search end with the player able to walk to target, for solving between two states of a level.
+    pub fn set_target_player(&mut self, target: Point2D) {
+        self.target_player = Some(target);
+    }
+
+    pub fn set_time_limit(&mut self, seconds: f32) {
+        self.time_limit = Duration::from_secs_f32(seconds);
+    }
+
+    // Gives up on finding a solution once more than max pushes would be needed.
+    pub fn set_max_pushes(&mut self, max: usize) {
+        self.max_bound = max;
+    }
+
+    // finds the squares the player can reach from the end node, which also normalizes the node's player position.
+    fn expand_player(&mut self) {
+        let node: &mut Node = self.path.last_mut().unwrap();
+        self.reach.fill(&self.static_map, &node.crates, node.player);
+        node.normalize(self.reach.player_norm);
+    }
+
+    // checks the end node against the rest of the path. If by chance there is a hash collision, states are still
+    // compared fully, so it only costs a little time.
+    fn is_duplicate(&self) -> bool {
+        let (node, rest) = self.path.split_last().unwrap();
+        rest.iter().any(|n| n.is_same_state(node))
     }
 
     // We know that crates will never be on the edge of the map.
@@ -311,63 +423,71 @@ impl IDAStarSolver {
     // Step cost is updated in here.
     fn successors(&mut self) -> Vec<Node> {
         let node: &Node = self.path.last().unwrap();
+        let width = self.static_map.width;
+        let mut succ_vec: Vec<Node> = Vec::new();
 
-        // find nodes player can access.
-        let mut walk_map = BitMatrix::new(node.map.width, node.map.data.len());
-        walk_map.set(node.player, true);
-        util::find_walkable_spaces(&node.map, node.player, &mut walk_map);
+        // crates in the goal room are packed, and only ever enter through the goal room macro.
+        if let Some(room) = &self.goal_room {
+            let filled = node.crates.iter().filter(|c| room.room.get(*c).unwrap()).count();
+            if node.crates.get(room.entrance) && filled < room.order.len() && 
+               self.reach.is_reachable(room.entrance.from(room.entry.inverse()), width) {
+                let mut new_crates = node.crates.clone();
+                new_crates.move_crate(room.entrance, room.order[filled]);
+
+                let pushes = room.macros[filled].len();
+                let (player_end, last_action) = room.macros[filled][pushes - 1];
+                let mut new_node = Node::make_new(last_action, pushes, new_crates, player_end, node.g + pushes);
+                new_node.goal_macro = Some(filled);
+                self.rundat.nodes_generated += 1;
+                new_node.h = self.heuristic.estimate_push(node.h, &new_node, room.entrance, room.order[filled]);
+                if new_node.h != usize::MAX {
+                    succ_vec.push(new_node);
+                }
+            }
+        }
 
-        // find all the actions the player can take.
-        let mut succ_vec: Vec<Node> = Vec::new();
-        for (i, crate_pos) in node.crates.iter().enumerate() {
-            let adjacent: Vec<Action> = vec![
-                Action::PushRight, Action::PushLeft, Action::PushDown, Action::PushUp 
-            ];
-
-            // check all four directions.
-            for action in adjacent {
-                let crate_end = crate_pos.from(action);
-                let end_tile = node.map.get(crate_end);
-                let push_start = crate_pos.from(action.inverse());
-                
-                let can_walk = walk_map.get(push_start).unwrap();
-                if !can_walk {
-                    continue;
-                } else if self.is_simple_deadlock(crate_end) {
-                    self.rundat.nodes_skipped += 1;
+        // find all the pushes the player can make.
+        for &(crate_pos, action) in &self.reach.faces {
+            if let Some(room) = &self.goal_room {
+                if room.room.get(crate_pos).unwrap() || (crate_pos == room.entrance && action == room.entry) {
                     continue;
                 }
+            }
 
-                match end_tile {
-                    Tile::Wall => (),
-                    Tile::Crate => (),
-                    Tile::CrateGoal => (),
-                    _ => {
-                        // create new sets of map & crate data. 
-                        let mut new_map = node.map.clone();
-                        new_map.apply_action_and_move(action, *crate_pos, &node.map, node.player);
-                    
-                        // This updates the position of the moved crate.
-                        let mut new_crates = node.crates.clone();
-                        new_crates[i] = crate_end.clone();
-
-                        // every push costs 1
-                        let mut new_node = Node::make_new(
-                            action, new_map, new_crates, crate_pos.clone(), node.g + 1
-                        );
-
-                        // ignore node if it is deadlocked.
-                        if !new_node.is_deadlocked(crate_end) {
-                            self.rundat.nodes_generated += 1;
-                            new_node.h = (self.heuristic)(&self, &new_node);
-                            succ_vec.push(new_node);
-                        } else {
-                            self.rundat.nodes_deadlocked += 1;
-                            continue;
-                        }
-                    }
+            let crate_end = crate_pos.from(action);
+            if self.is_simple_deadlock(crate_end) {
+                self.rundat.nodes_skipped += 1;
+                continue;
+            }
+
+            // create new crate data. 
+            let mut new_crates = node.crates.clone();
+            new_crates.move_crate(crate_pos, crate_end);
+
+            // tunnel macro -> push the crate through the tunnel in one node.
+            let mut crate_end = crate_end;
+            let mut player_end = crate_pos;
+            let mut pushes = 1;
+            while self.is_tunnel_push(&new_crates, action, player_end, crate_end) {
+                new_crates.move_crate(crate_end, crate_end.from(action));
+                player_end = crate_end;
+                crate_end = crate_end.from(action);
+                pushes += 1;
+            }
+
+            // every push costs 1
+            let mut new_node = Node::make_new(action, pushes, new_crates, player_end, node.g + pushes);
+
+            // ignore node if it is deadlocked, or the heuristic knows it can't be solved.
+            if !new_node.is_deadlocked(&self.static_map, crate_end) {
+                self.rundat.nodes_generated += 1;
+                new_node.h = self.heuristic.estimate_push(node.h, &new_node, crate_pos, crate_end);
+                if new_node.h != usize::MAX {
+                    succ_vec.push(new_node);
+                    continue;
                 }
             }
+            self.rundat.nodes_deadlocked += 1;
         }
 
         // sort by f cost?
@@ -385,50 +505,99 @@ impl IDAStarSolver {
 
     fn ida_star(&mut self) -> (Vec<Action>, usize, usize) {
         let mut bound = self.path.last().unwrap().h; // Oh damn, this is smart.
-        while self.solutions.is_empty() {
+        if bound == usize::MAX {
+            return (Vec::new(), 0, 0);  // the heuristic knows the start can't be solved.
+        }
+        while self.solutions == 0 {
+            if bound > self.max_bound {
+                return (Vec::new(), 0, bound);
+            }
             if self.debug {
                 println!("DEBUG: bound updated to {}", bound);
             }
             let new_f = self.search(bound);
-            if new_f == std::usize::MAX {
+            if new_f == usize::MAX && self.solutions == 0 {
                 return (Vec::new(), 0, bound);
             }
 
             bound = new_f;
         }
-        
-        if self.debug {
-            println!("DEBUG: starting A* ...");
-        }
-
-        // Find the shortest solution of push-len $bound by using A* to do previously assumed pathfinding.
-        let mut min_moves = std::usize::MAX;
-        let mut best_move_path: Vec<Action> = Vec::new();
-        for solution_path in &mut self.solutions {
-            // convert path of nodes to actions, then string.
-            let mut action_path: Vec<Action> = Vec::new();
-            for i in 1..solution_path.len() {
-                let pos_before = solution_path[i - 1].player;
-                let node = &mut solution_path[i];
-                let pos_after = node.player.from(node.action.inverse());
-
-                // for A*
-                node.map.undo_action(node.action, node.player);
-
-                // Using A* is better than IDA* here becase the puzzle is comparatively small, thus we can store all the
-                // nodes in memory. A* is also faster than IDA* because of its hard memory usage.
-                let mut actions: Vec<Action> = util::astar_pathfind(&node.map, node.action, pos_before, pos_after);
-                action_path.append(&mut actions);
-            }
 
-            // save min $path.len() of all solution paths constructed from $bound pushes.
-            if action_path.len() < min_moves {
-                min_moves = action_path.len();
-                best_move_path = action_path;
-            }
-        } 
+        if let Some(distinct) = &mut self.distinct {
+            distinct.add_best(&self.best_solution);
+        }
+        (self.best_solution.clone(), self.solutions, bound)
+    }
+
+    // Calls f with every solution found at the final bound, along with its actions. Each solution is only kept
+    // while it is being evaluated, so this is how tools can enumerate all of them. f can return false to end the
+    // search early, which keeps the best solution found so far.
+    pub fn on_solution(&mut self, f: SolutionCallback) {
+        self.on_solution = Some(f);
+    }
+
+    // Makes the next search list up to max distinct push optimal solutions, which distinct_solutions() returns once it
+    // is done. The search still finishes the final bound after max is reached, so that every listed solution & the 
+    // best one have the fewest moves.
+    pub fn collect_all_optimal(&mut self, max: usize) {
+        let root = &self.path[0];
+        let mut level = root.make_map(&self.static_map);
+        let tile = if self.static_map.get(root.player) == Tile::Goal { Tile::PlayerGoal } else { Tile::Player };
+        level.set(root.player, tile);
+        self.distinct = Some(DistinctSolutions::new(level, max));
+    }
+
+    pub fn distinct_solutions(&self) -> Option<&DistinctSolutions> {
+        self.distinct.as_ref()
+    }
+
+    // Runs the search, returning up to max distinct push optimal solutions.
+    pub fn solve_all_optimal(&mut self, max: usize) -> DistinctSolutions {
+        self.collect_all_optimal(max);
+        self.solve_actions();
+        self.distinct.take().unwrap()
+    }
+
+    // Finds the moves of the current path, which reached the goal, then keeps it if it is the best so far. Solutions 
+    // are evaluated as they are found, so that only one full path ever needs to be stored.
+    fn record_solution(&mut self) {
+        let IDAStarSolver { path, walks, static_map, goal_room, .. } = self;
+
+        // list the (crate position, push) steps made by each node.
+        let mut pushes: Vec<(Point2D, Action)> = Vec::new();
+        for node in &path[1..] {
+            match node.goal_macro {
+                Some(k) => pushes.extend_from_slice(&goal_room.as_ref().unwrap().macros[k]),
+                None => {
+                    // the player always ends up where the crate was before the last push.
+                    let start = pushes.len();
+                    let mut crate_start = node.player;
+                    pushes.push((crate_start, node.action));
+                    for _ in 1..node.pushes {
+                        crate_start = crate_start.from(node.action.inverse());
+                        pushes.push((crate_start, node.action));
+                    }
+                    pushes[start..].reverse();
+                },
+            };
+        }
+
+        // The walk between pushes only depends on the crates & the two squares, so walks are shared between
+        // solutions through the cache.
+        let action_path = util::walk_pushes(walks, static_map, path[0].crates.clone(), path[0].player, &pushes);
+
+        if let Some(f) = &mut self.on_solution {
+            self.stopped = !f(&self.path, &action_path);
+        }
+        if let Some(distinct) = &mut self.distinct {
+            distinct.add(&action_path);
+        }
 
-        return (best_move_path, self.solutions.len(), bound);
+        // save the solution with the fewest moves out of all solutions with $bound pushes.
+        if self.solutions == 0 || action_path.len() < self.best_solution.len() {
+            self.best_solution = action_path;
+        }
+        self.solutions += 1;
     }
 
     // adapted from https://en.wikipedia.org/wiki/Iterative_deepening_A*
@@ -436,12 +605,12 @@ impl IDAStarSolver {
         let node: &Node = self.path.last().unwrap();  // End node will always exist.
         let f_cost = node.g + node.h;  // estimated cost of the cheapest path (root..node..goal)
     
-        if self.rundat.nodes_checked % PER_NODE_TIME_CHECK == 0 && self.timer.elapsed().as_secs() > TIME_LIMIT {
+        if self.rundat.nodes_checked.is_multiple_of(PER_NODE_TIME_CHECK) && self.timer.elapsed() > self.time_limit {
             self.search_over = true;
         }
 
-        if self.search_over  {
-            return std::usize::MAX;
+        if self.search_over || self.stopped {
+            return usize::MAX;
         }
 
         self.rundat.nodes_checked += 1;
@@ -449,41 +618,159 @@ impl IDAStarSolver {
         // base cases
         if f_cost > bound { 
             return f_cost;  // end current dls
-        } else if self.is_goal(node) {
-            self.solutions.push(self.path.clone());
+        } else if self.is_goal() {
+            self.record_solution();
             return f_cost;  // this number doesn't matter.
-        } else if self.deadlock_hashing_on && self.deadlocks.contains(&node.map) {
-            return std::usize::MAX; // this means no solution will be found behind this.
         }
 
-        let mut min: usize = std::usize::MAX; // infinity
+        self.expand_player();
+        let node: &Node = self.path.last().unwrap();
+        if self.is_duplicate() {
+            return usize::MAX;
+        } else if self.deadlock_hashing_on && self.deadlocks.contains(&(node.crates.clone(), node.player_norm)) {
+            return usize::MAX; // this means no solution will be found behind this.
+        }
+
+        let mut min: usize = usize::MAX; // infinity
         for succ in self.successors() {
-            // if by chance there is a hash collision, the worst that will happen is a small
-            // performance hit. However this is extremely unlikely so its all good.
-            let mut is_duplicate = false;
-            for node in &self.path {
-                if node.hash == succ.hash {
-                    is_duplicate = true;
-                    break;
-                }
+            self.path.push(succ);
+            let new_f = self.search(bound);  // recursion
+            if new_f < min {
+                min = new_f;
             }
+            
+            // hitting this line means that none of this node's children are the goal. (within current bound)
+            // duplicates are skipped here, since they are still being searched further up the path.
+            let node = self.path.pop().unwrap();
+            if self.deadlock_hashing_on && min == usize::MAX && !self.path.iter().any(|n| n.is_same_state(&node)) {
+                self.deadlocks.insert((node.crates, node.player_norm));
+            }
+        }
+        
+        min
+    }
 
-            if !is_duplicate {
+    // Goal room macros fix the packing order of the goals, which may cost extra pushes. An inadmissible heuristic can
+    // also cut off the optimal solution.
+    pub fn is_push_optimal(&self) -> bool {
+        self.goal_room.is_none() && self.heuristic.is_admissible()
+    }
+
+    // When the time limit runs out, or the solution callback stops the search, after solutions were found at the final
+    // bound, the best one so far is kept. It is still push optimal (as far as the heuristic allows), but a solution
+    // with fewer moves may not have been reached.
+    pub fn is_move_optimal(&self) -> bool {
+        !((self.search_over || self.stopped) && self.solutions > 0)
+    }
+
+    // The best solution of the last search, if it found one.
+    pub fn solution(&self) -> Option<Vec<Action>> {
+        if self.solutions == 0 {
+            None
+        } else {
+            Some(self.best_solution.clone())
+        }
+    }
+
+    // Runs the search, returning the actions of the best solution, or None if there isn't one within the limits.
+    pub fn solve_actions(&mut self) -> Option<Vec<Action>> {
+        self.timer = Instant::now();
+        let (path, solutions, _) = self.ida_star();
+        if solutions == 0 {
+            None
+        } else {
+            Some(path)
+        }
+    }
+
+    // The heuristic's estimate for the starting state.
+    pub fn initial_h(&self) -> usize {
+        self.path[0].h
+    }
+
+    pub fn time_elapsed(&self) -> f32 {
+        self.timer.elapsed().as_secs_f32()
+    }
+
+    pub fn nodes_checked(&self) -> usize {
+        self.rundat.nodes_checked
+    }
+
+    // Runs IDA* iterations until the first solution or the time limit, returning the highest bound reached. Every
+    // iteration below the bound finished without a solution, so when the heuristic is admissible no solution can have
+    // fewer pushes than it. A solved level's bound is its push count.
+    pub fn lower_bound(&mut self) -> (usize, SearchEnd) {
+        self.timer = Instant::now();
+        self.on_solution(Box::new(|_, _| false));
+        let (_, solutions, bound) = self.ida_star();
+        if solutions > 0 {
+            (bound, SearchEnd::Solved)
+        } else if self.search_over {
+            (bound, SearchEnd::TimeLimit)
+        } else {
+            (bound, SearchEnd::Unsolvable)
+        }
+    }
+
+    // Breadth first search over every reachable state, with each state only expanded once. Unlike IDA*, which only
+    // remembers the current path, this either finds a solution or proves that none exists, as long as it fits within
+    // max_states & the time limit. Returns how the search ended, the number of states explored & the solution found.
+    pub fn exhaust(&mut self, max_states: usize) -> (SearchEnd, usize, Vec<Action>) {
+        self.timer = Instant::now();
+        self.path.truncate(1);
+        if self.is_goal() {
+            self.record_solution();
+            return (SearchEnd::Solved, 0, self.best_solution.clone());
+        }
+        self.expand_player();
+
+        let root = self.path[0].clone();
+        let mut closed: HashSet<(CrateSet, usize)> = HashSet::new();
+        closed.insert((root.crates.clone(), root.player_norm));
+        let mut nodes: Vec<(Node, usize)> = vec![(root, 0)];  // (node, parent index)
+        let mut queue: VecDeque<usize> = VecDeque::new();
+        queue.push_back(0);
+
+        while let Some(i) = queue.pop_front() {
+            if self.rundat.nodes_checked >= max_states {
+                return (SearchEnd::StateLimit, self.rundat.nodes_checked, Vec::new());
+            } else if self.rundat.nodes_checked.is_multiple_of(PER_NODE_TIME_CHECK) && self.timer.elapsed() > self.time_limit {
+                return (SearchEnd::TimeLimit, self.rundat.nodes_checked, Vec::new());
+            }
+            self.rundat.nodes_checked += 1;
+
+            self.path.clear();
+            self.path.push(nodes[i].0.clone());
+            self.expand_player();
+            for succ in self.successors() {
+                // checked on the path, so the goal is the same as in IDA*.
                 self.path.push(succ);
-                let new_f = self.search(bound);  // recursion
-                if new_f < min {
-                    min = new_f;
+                let solved = self.is_goal();
+                let mut succ = self.path.pop().unwrap();
+                if solved {
+                    // rebuild the path from the root to find the moves.
+                    let mut path = vec![succ];
+                    let mut parent = i;
+                    while parent != 0 {
+                        path.push(nodes[parent].0.clone());
+                        parent = nodes[parent].1;
+                    }
+                    path.push(nodes[0].0.clone());
+                    path.reverse();
+                    self.path = path;
+                    self.record_solution();
+                    return (SearchEnd::Solved, self.rundat.nodes_checked, self.best_solution.clone());
                 }
-                
-                // hitting this line means that none of this node's children are the goal. (within current bound)
-                let node = self.path.pop().unwrap();
-                if self.deadlock_hashing_on && min == std::usize::MAX {
-                    self.deadlocks.insert(node.map);
+
+                self.reach.fill(&self.static_map, &succ.crates, succ.player);
+                succ.normalize(self.reach.player_norm);
+                if closed.insert((succ.crates.clone(), succ.player_norm)) {
+                    queue.push_back(nodes.len());
+                    nodes.push((succ, i));
                 }
             }
         }
-        
-        return min;
+        (SearchEnd::Unsolvable, self.rundat.nodes_checked, Vec::new())
     }
 
     // currently just returns solution as string.
@@ -502,27 +789,135 @@ impl IDAStarSolver {
             self.rundat.print();
         }
 
-        if self.search_over {
+        // a search which ran out of time keeps the solutions it already found at the final bound.
+        if self.search_over && solutions == 0 {
             if self.debug {
                 return "time elapsed".to_string();
             } else {
-                return format!("{},{},{},{},{},{}", self.timer.elapsed().as_secs_f32(), self.rundat.nodes_checked, solutions, bound, path.len(), "".to_string());
+                return format!("{},{},{},{},{},{},{},{}", self.timer.elapsed().as_secs_f32(), self.rundat.nodes_checked, solutions, bound, path.len(), "", self.is_push_optimal(), self.is_move_optimal());
             }
         }
 
-        if path.len() == 0 {
+        if path.is_empty() {
             if self.debug {
                 return "no solution".to_string();
             } else {
-                return format!("{},{},{},{},{},{}", self.timer.elapsed().as_secs_f32(), self.rundat.nodes_checked, solutions, bound, path.len(), "".to_string());
+                return format!("{},{},{},{},{},{},{},{}", self.timer.elapsed().as_secs_f32(), self.rundat.nodes_checked, solutions, bound, path.len(), "", self.is_push_optimal(), self.is_move_optimal());
             }
         }
 
         if self.debug {
-            return Action::to_string(&path);
+            Action::to_string(&path)
         } else {
-            return format!("{},{},{},{},{},{}", self.timer.elapsed().as_secs_f32(), self.rundat.nodes_checked, solutions, bound, path.len(), Action::to_string(&path));
+            format!("{},{},{},{},{},{},{},{}", self.timer.elapsed().as_secs_f32(), self.rundat.nodes_checked, solutions, bound, path.len(), Action::to_string(&path), self.is_push_optimal(), self.is_move_optimal())
         }
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heuristic::{GoalCount, Hungarian};
+    use crate::level_reader::Collection;
+    use std::rc::Rc;
+    use std::cell::Cell;
+
+    fn exhaust(level: &str) -> (SearchEnd, Vec<Action>) {
+        let level = TileMatrix::from_string(level);
+        let mut solver = IDAStarSolver::new(level, Box::new(GoalCount::default()), false, false, false);
+        let (end, _, actions) = solver.exhaust(10_000);
+        (end, actions)
+    }
+
+    #[test]
+    fn exhaust_proves_unsolvable() {
+        // neither crate is on a simple deadlock square, but they pin each other against the wall.
+        let (end, actions) = exhaust("######\n#@$$.#\n#   .#\n######\n");
+        assert_eq!(end, SearchEnd::Unsolvable);
+        assert!(actions.is_empty());
+    }
+
+    #[test]
+    fn exhaust_finds_solution() {
+        let level = "#######\n#     #\n# @$ .#\n# $  .#\n#     #\n#######\n";
+        let (end, actions) = exhaust(level);
+        assert_eq!(end, SearchEnd::Solved);
+        assert!(util::verify_solution(&TileMatrix::from_string(level), &actions).is_ok());
+    }
+
+    // the pushes of the solution to a level from levels/, after checking that it solves the level, & whether a macro 
+    // node was used to find it.
+    fn solve_level(sok: &str, level: usize, goal_room_macros: bool) -> (usize, bool) {
+        let puzzle = Collection::from_string(sok).unwrap().levels.remove(level).puzzle;
+        let mut solver = IDAStarSolver::new(puzzle.clone(), Box::new(Hungarian::default()), false, goal_room_macros, false);
+        let used_macro = Rc::new(Cell::new(false));
+        let seen = used_macro.clone();
+        solver.on_solution(Box::new(move |path, _| {
+            seen.set(seen.get() || path.iter().any(|node| node.pushes > 1 || node.goal_macro.is_some()));
+            true
+        }));
+        let actions = solver.solve_actions().unwrap();
+        (util::verify_solution(&puzzle, &actions).unwrap().0, used_macro.get())
+    }
+
+    #[test]
+    fn tunnel_macros_stay_push_optimal() {
+        assert_eq!(solve_level(include_str!("../levels/tunnels.sok"), 0, false), (12, true));
+    }
+
+    #[test]
+    fn goal_room_macro_solutions_verify() {
+        // the macros fix the packing order, so they can only match or exceed the optimum of 19 pushes. The second level
+        // is left out, since it takes a while to finish the final bound in a debug build.
+        let (pushes, used_macro) = solve_level(include_str!("../levels/goal_room.sok"), 0, true);
+        assert!(used_macro);
+        assert!(pushes >= 19);
+    }
+
+    // whether the crate at moved makes a closed diagonal deadlock in level's starting state.
+    fn closed_diagonal(level: &str, moved: Point2D) -> bool {
+        let solver = IDAStarSolver::new(TileMatrix::from_string(level), Box::new(GoalCount::default()), false, false, false);
+        solver.path[0].is_deadlocked(&solver.static_map, moved)
+    }
+
+    #[test]
+    fn push_closing_diagonal_is_pruned() {
+        // the crate just pushed up to (4, 4) closes the diamond around (4, 3).
+        let level = "#########\n#       #\n#  #$   #\n#  $ $  #\n#   $#  #\n#   @   #\n#  ....##\n#########\n";
+        assert!(closed_diagonal(level, Point2D::new(4, 4)));
+        // with the top of the diamond open, the same push is fine.
+        assert!(!closed_diagonal(&level.replace("#  #$   #", "#  # $  #"), Point2D::new(4, 4)));
+    }
+
+    #[test]
+    fn closed_diagonal_around_goal_with_one_loose_crate_is_kept() {
+        // the loose crate can still be pushed into the goal in the middle.
+        let level = "#########\n#       #\n#  #*   #\n#  *.*  #\n#   $#  #\n#   @   #\n#       #\n#########\n";
+        assert!(!closed_diagonal(level, Point2D::new(4, 4)));
+    }
+
+    #[test]
+    fn all_optimal_lists_distinct_push_paths() {
+        // the crate can take its two pushes down & one push right in any of 3 orders.
+        let level = TileMatrix::from_string("######\n#    #\n# @$ #\n#    #\n#   .#\n######\n");
+        let solve = |max: usize| {
+            let mut solver = IDAStarSolver::new(level.clone(), Box::new(Hungarian::default()), false, false, false);
+            let distinct = solver.solve_all_optimal(max);
+            (distinct, solver.solution().unwrap())
+        };
+
+        let (distinct, _) = solve(10);
+        assert_eq!(distinct.solutions.len(), 3);
+        assert!(!distinct.capped);
+        for actions in &distinct.solutions {
+            assert_eq!(util::verify_solution(&level, actions).unwrap().0, 3);
+        }
+
+        // past the cap, the best solution is still listed.
+        let (distinct, best) = solve(2);
+        assert_eq!(distinct.solutions.len(), 2);
+        assert!(distinct.capped);
+        assert!(distinct.solutions.contains(&best));
+    }
+}
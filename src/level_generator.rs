@@ -1,11 +1,44 @@
 use std::fs::File;
 use std::io::Write;
 use std::process;
+use std::cmp::Reverse;
+use std::collections::HashSet;
 
 use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
 
 use crate::util;
-use crate::types::{TileMatrix, Point2D, BitMatrix};
+use crate::types::{Tile, TileMatrix, Point2D, BitMatrix, Action};
+use crate::heuristic::{Heuristic, HeuristicRegistry, GoalCount};
+use crate::ida_star_solver::{IDAStarSolver, SearchEnd};
+
+const MAX_PROOF_STATES: usize = 200_000;  // states searched per puzzle when only keeping solvable puzzles.
+const MAX_DUPLICATES: usize = 1000;  // duplicates in a row before deduplicated generation gives up.
+
+// Random indices are drawn as u32. rand draws a different number of bits for a usize range on 32 & 64 bit targets, 
+// which would make different puzzles from the same seed.
+fn gen_index(rng: &mut ChaCha8Rng, low: usize, high: usize) -> usize {
+    rng.gen_range(low as u32, high as u32) as usize
+}
+
+fn choose<'a, T>(rng: &mut ChaCha8Rng, items: &'a [T]) -> Option<&'a T> {
+    if items.is_empty() {
+        None
+    } else {
+        Some(&items[gen_index(rng, 0, items.len())])
+    }
+}
+
+// n different items, in a random order.
+fn choose_multiple<T: Copy>(rng: &mut ChaCha8Rng, items: &[T], n: usize) -> Vec<T> {
+    let mut items = items.to_vec();
+    for i in 0..n.min(items.len()) {
+        let j = gen_index(rng, i, items.len());
+        items.swap(i, j);
+    }
+    items.truncate(n);
+    items
+}
 
 // Generate levels based on:
 // 1. dimensions -> H x W
@@ -29,97 +62,318 @@ fn init_puzzle(width: usize, height: usize) -> Vec<char> {
     puzzle_vec
 }
 
-pub fn make_sok(file_name: &str, width: usize, height: usize, batch_num: usize, goal_num: usize, wall_num: usize) {
-    let mut rng = rand::thread_rng();
-    let mut file_string = format!("Date of Last Change:\n\nSet: {}\nCopyright: Geb\nEmail:\nHomepage:\n\n\
-                                   This sokoban puzzle set was automatically generated\n\n", file_name);
-    let inner_spaces = (width - 2) * (height - 2);
+// The shape puzzles are generated in. Walls & fixed goals are kept as they are, & the generator fills the open floor.
+#[derive(Clone)]
+pub struct Template {
+    pub width: usize,
+    pub height: usize,
+    squares: Vec<char>,  // '#' wall, '.' fixed goal or ' ' open floor, with a newline after each row.
+}
+impl Template {
+    // a rectangle walled on its border.
+    pub fn rectangle(width: usize, height: usize) -> Template {
+        Template { width, height, squares: init_puzzle(width, height) }
+    }
 
-    let mut i = 0;
-    while i < batch_num {
-        let mut puzzle_vec: Vec<char> = init_puzzle(width, height);
+    // Reads a template drawn like a puzzle, with '#' for walls, '.' for fixed goals & ' ', '-' or '_' for floor. 
+    // Floor which isn't enclosed by walls is outside of the puzzle, so any shape can be drawn.
+    pub fn from_string(template_string: &str) -> Result<Template, String> {
+        let lines: Vec<&str> = template_string.lines().filter(|line| !line.trim().is_empty()).collect();
+        let height = lines.len();
+        let width = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+        if width < 3 || height < 3 {
+            return Err("template must be at least 3x3".to_string());
+        }
 
-        let mut goals: Vec<Point2D> = Vec::new();
-        let mut goals_added = 0;
-        while goals_added < goal_num {
-            let index = rng.gen_range(0, inner_spaces);
-            let (x, y) = ((index % (width - 2)) + 1, (index / (width - 2)) + 1);
-            let index = x + y * (width + 1);
-            if puzzle_vec[index] == ' ' {
-                puzzle_vec[index] = '.';
-                goals_added += 1;
-                goals.push( Point2D::new(x, y) );
+        let mut template = Template { width, height, squares: Vec::new() };
+        for line in &lines {
+            for x in 0..width {
+                template.squares.push(match line.chars().nth(x).unwrap_or(' ') {
+                    '#' => '#',
+                    '.' => '.',
+                    ' ' | '-' | '_' => ' ',
+                    ch => return Err(format!("invalid character in template, \"{}\". template can only contain \"#. -_\"", ch)),
+                });
             }
+            template.squares.push('\n');
         }
 
-        let mut walls_added = 0;
-        while walls_added < wall_num {
-            let index = rng.gen_range(0, inner_spaces);
-            let (x, y) = ((index % (width - 2)) + 1, (index / (width - 2)) + 1);
-            let index = x + y * (width + 1);
-            if puzzle_vec[index] == ' ' {
-                puzzle_vec[index] = '#';
-                walls_added += 1;
+        // flood fill from the edges to find the outside.
+        let mut stack: Vec<Point2D> = (0..height).flat_map(|y| (0..width).map(move |x| Point2D::new(x, y)))
+            .filter(|p| p.x == 0 || p.y == 0 || p.x == width - 1 || p.y == height - 1)
+            .collect();
+        while let Some(p) = stack.pop() {
+            let index = template.index(p);
+            match template.squares[index] {
+                '#' => continue,
+                '.' => return Err("fixed goals must be enclosed by walls".to_string()),
+                _ => template.squares[index] = '#',
+            }
+            for action in [Action::Up, Action::Down, Action::Left, Action::Right] {
+                let (x, y) = (p.x as isize + dx(action), p.y as isize + dy(action));
+                if x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height {
+                    stack.push(Point2D::new(x as usize, y as usize));
+                }
             }
         }
+        Ok(template)
+    }
 
-        // Check all locations which can be pulled to.
-        let mut puzzle_string = String::new();
-        for ch in &puzzle_vec {
-            puzzle_string.push(*ch);
+    // Carves a few rectangular rooms out of solid wall, then joins each room to the next with a one-wide corridor.
+    pub fn rooms(rng: &mut ChaCha8Rng, width: usize, height: usize) -> Template {
+        let mut template = Template { width, height, squares: Vec::new() };
+        for _ in 0..height {
+            template.squares.resize(template.squares.len() + width, '#');
+            template.squares.push('\n');
         }
-        let tile_map = TileMatrix::from_string_bare(&puzzle_string[..]);
-        let good_spaces: BitMatrix = util::find_simple_deadlocks(&tile_map, &goals);
 
-        // check if there are enough spaces for the crates to go in.
-        let mut crate_spaces = 0;
-        for y in 0..height {
-            for x in 0..width {
-                if good_spaces.get(Point2D::new(x, y)).unwrap()
-                   && puzzle_vec[x + y * (width+1)] == ' ' {
-                    crate_spaces += 1;
+        let room_num = 2 + (width * height) / 60;
+        let mut centers: Vec<Point2D> = Vec::new();
+        for _ in 0..room_num {
+            let room_width = gen_index(rng, 2, 5).min(width - 2);
+            let room_height = gen_index(rng, 2, 5).min(height - 2);
+            let left = gen_index(rng, 1, width - room_width);
+            let top = gen_index(rng, 1, height - room_height);
+            for y in top..top + room_height {
+                for x in left..left + room_width {
+                    let index = template.index(Point2D::new(x, y));
+                    template.squares[index] = ' ';
                 }
             }
-        }
-        
-        if crate_spaces < goal_num {
-            println!("Could not assign crates, retrying current puzzle...");
-            continue;
+            centers.push(Point2D::new(left + room_width / 2, top + room_height / 2));
         }
 
-        let mut crates_added = 0;
-        while crates_added < goal_num {
-            let index = rng.gen_range(0, inner_spaces);
-            let (x, y) = ((index % (width - 2)) + 1, (index / (width - 2)) + 1);
-            let index = x + y * (width + 1);
-            if puzzle_vec[index] == ' ' && good_spaces.get(Point2D::new(x, y)).unwrap() {
-                puzzle_vec[index] = '$';
-                crates_added += 1;
+        // corridors go across, then up or down.
+        for pair in centers.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            for x in a.x.min(b.x)..=a.x.max(b.x) {
+                let index = template.index(Point2D::new(x, a.y));
+                template.squares[index] = ' ';
+            }
+            for y in a.y.min(b.y)..=a.y.max(b.y) {
+                let index = template.index(Point2D::new(b.x, y));
+                template.squares[index] = ' ';
             }
         }
+        template
+    }
+
+    fn index(&self, p: Point2D) -> usize {
+        p.x + p.y * (self.width + 1)
+    }
 
-        let mut player_added = false;
-        while !player_added {
-            let index = rng.gen_range(0, inner_spaces);
-            let (x, y) = ((index % (width - 2)) + 1, (index / (width - 2)) + 1);
-            let index = x + y * (width + 1);
-            if puzzle_vec[index] == ' ' {
-                puzzle_vec[index] = '@';
-                player_added = true;
-            } else if puzzle_vec[index] == '.' {
-                puzzle_vec[index] = '+';
-                player_added = true;
+    // squares the generator can place things on.
+    pub fn open_squares(&self) -> Vec<Point2D> {
+        (0..self.height).flat_map(|y| (0..self.width).map(move |x| Point2D::new(x, y)))
+            .filter(|p| self.squares[self.index(*p)] == ' ')
+            .collect()
+    }
+
+    pub fn fixed_goals(&self) -> Vec<Point2D> {
+        (0..self.height).flat_map(|y| (0..self.width).map(move |x| Point2D::new(x, y)))
+            .filter(|p| self.squares[self.index(*p)] == '.')
+            .collect()
+    }
+
+    // whether there is room for the goals, walls, crates & player.
+    pub fn fits(&self, goal_num: usize, wall_num: usize) -> bool {
+        let goals_added = goal_num.saturating_sub(self.fixed_goals().len());
+        let crates = goal_num.max(self.fixed_goals().len());
+        self.open_squares().len() > goals_added + wall_num + crates
+    }
+}
+
+fn dx(action: Action) -> isize {
+    match action {
+        Action::Left => -1,
+        Action::Right => 1,
+        _ => 0,
+    }
+}
+
+fn dy(action: Action) -> isize {
+    match action {
+        Action::Up => -1,
+        Action::Down => 1,
+        _ => 0,
+    }
+}
+
+// The outline of the puzzles being generated.
+pub enum Shape {
+    Rectangle,
+    Rooms,
+    File(Template),
+}
+impl Shape {
+    fn template(&self, rng: &mut ChaCha8Rng, width: usize, height: usize) -> Template {
+        match self {
+            Shape::Rectangle => Template::rectangle(width, height),
+            Shape::Rooms => Template::rooms(rng, width, height),
+            Shape::File(template) => template.clone(),
+        }
+    }
+}
+
+// Searches the puzzle's state space to check that it has a solution. Puzzles too large to search count as unsolvable.
+pub fn is_solvable(puzzle: &TileMatrix) -> bool {
+    let mut solver = IDAStarSolver::new(puzzle.clone(), Box::new(GoalCount::default()), false, false, false);
+    let (end, _, _) = solver.exhaust(MAX_PROOF_STATES);
+    end == SearchEnd::Solved
+}
+
+// Tidies a generated puzzle. Floor the player can never reach is walled in, walls that don't border any walkable
+// square are taken out, & the puzzle is trimmed to the walls around it. Errors on puzzles which are broken or
+// already solved, so they can be retried.
+pub fn clean_up(puzzle: &TileMatrix) -> Result<TileMatrix, &'static str> {
+    let width = puzzle.width;
+    let height = puzzle.data.len() / width;
+    let walkable = util::find_walkable_spaces(puzzle);
+    let is_walkable = |x: usize, y: usize| walkable.get(Point2D::new(x, y)).unwrap();
+    if (0..width).any(|x| is_walkable(x, 0) || is_walkable(x, height - 1))
+       || (0..height).any(|y| is_walkable(0, y) || is_walkable(width - 1, y)) {
+        return Err("The player can walk off the edge of the puzzle");
+    }
+
+    // a crate stuck on a goal is as good as a wall, but a lone crate or goal makes the puzzle unsolvable.
+    let mut cleaned = puzzle.clone();
+    for (i, tile) in cleaned.data.iter_mut().enumerate() {
+        if !is_walkable(i % width, i / width) {
+            match tile {
+                Tile::Crate | Tile::Goal => return Err("Crates or goals are cut off from the player"),
+                _ => *tile = Tile::Wall,
             }
         }
+    }
+    if !cleaned.data.contains(&Tile::Crate) {
+        return Err("Every crate starts on a goal");
+    }
 
-        // add to string
-        file_string.push_str(&format!("{}\n", i+1));
-        for ch in puzzle_vec {
-            file_string.push(ch);
+    // walls with no walkable square around them, counting corners, become the outside.
+    let (mut left, mut right, mut top, mut bottom) = (width, 0, height, 0);
+    for y in 0..height {
+        for x in 0..width {
+            let near_walkable = (y.saturating_sub(1)..(y + 2).min(height))
+                .any(|ny| (x.saturating_sub(1)..(x + 2).min(width)).any(|nx| is_walkable(nx, ny)));
+            if near_walkable {
+                left = left.min(x);
+                right = right.max(x);
+                top = top.min(y);
+                bottom = bottom.max(y);
+            } else {
+                cleaned.set(Point2D::new(x, y), Tile::Floor);
+            }
         }
-        file_string.push_str("\n\n");
+    }
 
-        i += 1;
+    let mut data: Vec<Tile> = Vec::new();
+    for y in top..=bottom {
+        data.extend_from_slice(&cleaned.data[y * width + left..=y * width + right]);
+    }
+    Ok(TileMatrix { width: right - left + 1, data })
+}
+
+// How puzzles are made.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum GenMode {
+    Random,  // goals, walls, crates & the player are all placed at random.
+    Reverse,  // crates start on the goals & are pulled away, so every puzzle has a solution.
+    Evolve,  // reverse puzzles are mutated over generations, keeping the ones with the longest solutions.
+}
+
+pub struct GenSettings {
+    pub width: usize,  // the size of rectangle & rooms shapes.
+    pub height: usize,
+    pub shape: Shape,
+    pub batch_num: usize,
+    pub goal_num: usize,
+    pub wall_num: usize,
+    pub mode: GenMode,
+    pub pulls: usize,  // how many pulls reverse mode tries to make.
+    pub solvable_only: bool,
+    pub dedupe: bool,  // skip puzzles with the same canonical form as one already written.
+    pub targets: Option<DifficultyTargets>,  // when set, puzzles are solved & only kept if they hit the targets.
+    pub heuristic: String,  // used whenever puzzles are solved to measure them.
+    pub time_limit: f32,  // seconds each puzzle is given to be solved.
+    pub max_attempts: usize,  // puzzles in a row which can be thrown away before generation gives up.
+    pub population: usize,  // puzzles in each generation of evolve mode.
+    pub generations: usize,
+    pub seed: Option<u64>,  // picked at random when not given.
+    pub level_seed: Option<u64>,  // makes only the puzzle with this seed, as written under each puzzle.
+}
+
+// Inclusive (min, max) ranges that a puzzle's solution must fall in. The solution is optimal unless the heuristic is
+// inadmissible, or the time limit cut the search short.
+pub struct DifficultyTargets {
+    pub pushes: (usize, usize),
+    pub moves: (usize, usize),
+    pub nodes: (usize, usize),
+}
+impl DifficultyTargets {
+    pub fn contains(&self, difficulty: &Difficulty) -> bool {
+        self.missed(difficulty).is_empty()
+    }
+
+    // (name, target range, measured value) of each target the puzzle is outside of.
+    pub fn missed(&self, difficulty: &Difficulty) -> Vec<(&'static str, (usize, usize), usize)> {
+        [("pushes", self.pushes, difficulty.pushes), ("moves", self.moves, difficulty.moves), ("nodes", self.nodes, difficulty.nodes)]
+            .iter()
+            .copied()
+            .filter(|(_, (min, max), n)| n < min || n > max)
+            .collect()
+    }
+}
+
+// a target range as it is written on the command line.
+fn range_string((min, max): (usize, usize)) -> String {
+    match (min, max) {
+        (0, usize::MAX) => "..".to_string(),
+        (min, usize::MAX) => format!("{}..", min),
+        (0, max) => format!("..{}", max),
+        (min, max) => format!("{}..{}", min, max),
+    }
+}
+
+// How hard a puzzle was for the solver.
+pub struct Difficulty {
+    pub pushes: usize,
+    pub moves: usize,
+    pub nodes: usize,
+    pub optimal: bool,  // false when the heuristic is inadmissible, or time ran out before the fewest moves were found.
+}
+
+// Solves the puzzle to measure its difficulty, returning it with the solution. None if it isn't solved in time.
+pub fn measure(puzzle: &TileMatrix, heuristic: Box<dyn Heuristic>, time_limit: f32) -> Option<(Difficulty, Vec<Action>)> {
+    let mut solver = IDAStarSolver::new(puzzle.clone(), heuristic, false, false, false);
+    solver.set_time_limit(time_limit);
+    let solution = solver.solve_actions()?;
+    let difficulty = Difficulty {
+        pushes: solution.iter().filter(|a| a.is_push()).count(),
+        moves: solution.len(),
+        nodes: solver.nodes_checked(),
+        optimal: solver.is_push_optimal() && solver.is_move_optimal(),
+    };
+    Some((difficulty, solution))
+}
+
+// settings.heuristic is made from registry, which can hold heuristics from outside the crate.
+pub fn make_sok(file_name: &str, settings: &GenSettings, registry: &HeuristicRegistry) {
+    let seed = settings.seed.unwrap_or_else(rand::random);
+    // with --level-seed, the set's seed isn't used, so the puzzle's own seed is written instead.
+    let seed_line = match settings.level_seed {
+        Some(level_seed) => format!("Level seed: {}", level_seed),
+        None => format!("Seed: {}", seed),
+    };
+    let mut file_string = format!("Date of Last Change:\n\nSet: {}\nCopyright: Geb\nEmail:\nHomepage:\n\n\
+                                   This sokoban puzzle set was automatically generated\n{}\n\n", file_name, seed_line);
+
+    if settings.mode == GenMode::Evolve {
+        // the generations written so far are kept.
+        if let Err(report) = evolve(&mut ChaCha8Rng::seed_from_u64(seed), settings, registry, &mut file_string) {
+            println!("{}", report);
+        }
+    } else {
+        generate(seed, settings, registry, &mut file_string);
     }
 
     // write string to file.
@@ -139,4 +393,553 @@ pub fn make_sok(file_name: &str, width: usize, height: usize, batch_num: usize,
             process::exit(1);
         },
     };
-}
\ No newline at end of file
+}
+// Makes batch_num puzzles one at a time, retrying any which don't meet the settings.
+fn generate(seed: u64, settings: &GenSettings, registry: &HeuristicRegistry, file_string: &mut String) {
+    let batch_num = if settings.level_seed.is_some() { 1 } else { settings.batch_num };
+    let mut written: HashSet<u64> = HashSet::new();
+    let mut seed_index = 0;  // duplicates are skipped by moving on to the next seed.
+    for i in 0..batch_num {
+        let mut duplicates = 0;
+        loop {
+            let level_seed = settings.level_seed.unwrap_or_else(|| derive_seed(seed, seed_index));
+            seed_index += 1;
+            let generated = generate_puzzle(&mut ChaCha8Rng::seed_from_u64(level_seed), settings, registry);
+            let (puzzle, solution, difficulty) = match generated {
+                Ok(generated) => generated,
+                Err(report) => {
+                    println!("{}", report);
+                    println!("Could not make puzzle {} with these settings, only {} were written.", i+1, i);
+                    return;
+                },
+            };
+            if settings.dedupe && !written.insert(puzzle.canonical_hash()) {
+                duplicates += 1;
+                if duplicates == MAX_DUPLICATES {
+                    println!("Could not find {} unique puzzles with these settings, only {} were written.", batch_num, i);
+                    return;
+                }
+                println!("Puzzle is a duplicate, retrying current puzzle...");
+                continue;
+            }
+            write_level(file_string, i+1, &puzzle, solution.as_ref(), difficulty.as_ref(), Some(format!("Seed: {}", level_seed)));
+            break;
+        }
+    }
+}
+
+// Each puzzle gets its own seed, so that it can be made again by itself.
+fn derive_seed(seed: u64, index: usize) -> u64 {
+    // splitmix64, which spreads out nearby seeds & indices.
+    let mut z = seed.wrapping_add((index as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+// A puzzle with its solution & measured difficulty if known.
+type GeneratedPuzzle = (TileMatrix, Option<Vec<Action>>, Option<Difficulty>);
+// (target, range, puzzles that missed it, min & max measured value)
+type TargetMiss = (&'static str, (usize, usize), usize, usize, usize);
+
+// Makes puzzles until one meets the settings, or gives up after max_attempts with a report of why they failed.
+fn generate_puzzle(rng: &mut ChaCha8Rng, settings: &GenSettings, registry: &HeuristicRegistry) 
+                   -> Result<GeneratedPuzzle, String> {
+    // why attempts failed, which is reported if this puzzle is given up on.
+    let mut missed: Vec<TargetMiss> = Vec::new();
+    let mut unsolved = 0;
+    let mut unproven = 0;
+    for _ in 0..settings.max_attempts {
+        let template = settings.shape.template(rng, settings.width, settings.height);
+        if !template.fits(settings.goal_num, settings.wall_num) {
+            println!("Shape is too small, retrying current puzzle...");
+            continue;
+        }
+        let generated = match settings.mode {
+            GenMode::Reverse => reverse_puzzle(rng, settings, &template).map(|(puzzle, solution)| (puzzle, Some(solution))),
+            _ => random_puzzle(rng, settings, &template).map(|puzzle| (puzzle, None)),
+        };
+        let (puzzle, mut solution) = match generated {
+            Some(generated) => generated,
+            None => continue,
+        };
+        let puzzle = match clean_up(&puzzle) {
+            Ok(puzzle) => puzzle,
+            Err(reason) => {
+                println!("{}, retrying current puzzle...", reason);
+                continue;
+            },
+        };
+
+        // the solver's solution replaces the one the generator knows, since it is optimal when the heuristic is admissible.
+        let mut difficulty: Option<Difficulty> = None;
+        if let Some(targets) = &settings.targets {
+            match measure(&puzzle, registry.make(&settings.heuristic).unwrap(), settings.time_limit) {
+                Some((measured, solved)) if targets.contains(&measured) => {
+                    difficulty = Some(measured);
+                    solution = Some(solved);
+                },
+                Some((measured, _)) => {
+                    let misses = targets.missed(&measured);
+                    let described: Vec<String> = misses.iter()
+                        .map(|(name, range, n)| format!("{} {} not in {}", name, n, range_string(*range)))
+                        .collect();
+                    println!("Puzzle is outside the difficulty targets ({}), retrying current puzzle...", described.join(", "));
+                    for (name, range, n) in misses {
+                        match missed.iter_mut().find(|m| m.0 == name) {
+                            Some(m) => *m = (name, range, m.2 + 1, m.3.min(n), m.4.max(n)),
+                            None => missed.push((name, range, 1, n, n)),
+                        }
+                    }
+                    continue;
+                },
+                None => {
+                    println!("Puzzle was not solved within the time limit, retrying current puzzle...");
+                    unsolved += 1;
+                    continue;
+                },
+            }
+        } else if settings.solvable_only && solution.is_none() && !is_solvable(&puzzle) {
+            println!("Puzzle could not be proven solvable, retrying current puzzle...");
+            unproven += 1;
+            continue;
+        }
+
+        return Ok((puzzle, solution, difficulty));
+    }
+
+    let mut reasons: Vec<String> = missed.iter()
+        .map(|(name, range, count, min, max)| {
+            format!("{} puzzles missed {} {}, with {}..{} {}", count, name, range_string(*range), min, max, name)
+        })
+        .collect();
+    if unsolved > 0 {
+        reasons.push(format!("{} puzzles were not solved within the time limit", unsolved));
+    }
+    if unproven > 0 {
+        reasons.push(format!("{} puzzles could not be proven solvable", unproven));
+    }
+    if reasons.is_empty() {
+        reasons.push("no puzzle could be placed in the shape".to_string());
+    }
+    Err(format!("Gave up after {} attempts: {}.", settings.max_attempts, reasons.join("; ")))
+}
+
+// adds a numbered puzzle to the .sok file string, along with what is known about it.
+fn write_level(file_string: &mut String, number: usize, puzzle: &TileMatrix, solution: Option<&Vec<Action>>,
+               difficulty: Option<&Difficulty>, comment: Option<String>) {
+    file_string.push_str(&format!("{}\n{}", number, puzzle));
+    if let Some(comment) = comment {
+        file_string.push_str(&format!("{}\n", comment));
+    }
+    if let Some(solution) = solution {
+        file_string.push_str(&format!("Solution: {}\n", Action::to_string(solution)));
+    }
+    if let Some(d) = difficulty {
+        let label = if d.optimal { "" } else { " (solution may not be optimal)" };
+        file_string.push_str(&format!("Difficulty: pushes {}, moves {}, nodes checked {}{}\n", d.pushes, d.moves, d.nodes, label));
+    }
+    file_string.push_str("\n\n");
+}
+
+// A puzzle in the evolving population, along with its measured difficulty & solution if it was solved in time.
+struct Individual {
+    puzzle: TileMatrix,
+    measured: Option<(Difficulty, Vec<Action>)>,
+}
+impl Individual {
+    fn new(puzzle: TileMatrix, settings: &GenSettings, registry: &HeuristicRegistry) -> Individual {
+        let measured = measure(&puzzle, registry.make(&settings.heuristic).unwrap(), settings.time_limit);
+        Individual { puzzle, measured }
+    }
+
+    // puzzles needing more pushes are fitter, with nodes checked breaking ties. Unsolved puzzles are the least fit.
+    fn fitness(&self) -> (usize, usize) {
+        match &self.measured {
+            Some((difficulty, _)) => (difficulty.pushes, difficulty.nodes),
+            None => (0, 0),
+        }
+    }
+}
+
+// Breeds harder puzzles by mutating reverse generated ones. The fitter half of each generation survives & the rest is
+// replaced by mutated copies of survivors. The batch_num best solved puzzles of every generation are written out.
+// Gives up with an error when max_attempts puzzles in a row can't be made for the population.
+fn evolve(rng: &mut ChaCha8Rng, settings: &GenSettings, registry: &HeuristicRegistry, file_string: &mut String) 
+          -> Result<(), String> {
+    let mut population: Vec<Individual> = Vec::new();
+    let (mut too_small, mut failed) = (0, 0);
+    while population.len() < settings.population {
+        if too_small + failed == settings.max_attempts {
+            return Err(format!("Gave up on the starting population after {} attempts: {} shapes were too small & {} \
+                                puzzles could not be made.", settings.max_attempts, too_small, failed));
+        }
+        let template = settings.shape.template(rng, settings.width, settings.height);
+        if !template.fits(settings.goal_num, settings.wall_num) {
+            too_small += 1;
+            continue;
+        }
+        match reverse_puzzle(rng, settings, &template).map(|(puzzle, _)| clean_up(&puzzle)) {
+            Some(Ok(puzzle)) => {
+                population.push(Individual::new(puzzle, settings, registry));
+                too_small = 0;
+                failed = 0;
+            },
+            _ => failed += 1,
+        }
+    }
+
+    let mut number = 1;
+    let mut written: HashSet<u64> = HashSet::new();
+    for generation in 1..=settings.generations {
+        population.sort_by_key(|individual| Reverse(individual.fitness()));
+        let (pushes, nodes) = population[0].fitness();
+        println!("Generation {}: best puzzle has {} pushes & took {} nodes", generation, pushes, nodes);
+
+        for (rank, individual) in population.iter().take(settings.batch_num).enumerate() {
+            if let Some((difficulty, solution)) = &individual.measured {
+                // survivors are carried into the next generation, so they would be written again.
+                if settings.dedupe && !written.insert(individual.puzzle.canonical_hash()) {
+                    continue;
+                }
+                let comment = format!("Generation {}, rank {}", generation, rank + 1);
+                write_level(file_string, number, &individual.puzzle, Some(solution), Some(difficulty), Some(comment));
+                number += 1;
+            }
+        }
+        if generation == settings.generations {
+            break;
+        }
+
+        population.truncate((settings.population / 2).max(1));
+        let survivors = population.len();
+        let mut failed = 0;
+        while population.len() < settings.population {
+            if failed == settings.max_attempts {
+                return Err(format!("Gave up on generation {} after {} mutated puzzles in a row could not be cleaned up.", 
+                                   generation + 1, settings.max_attempts));
+            }
+            let mut puzzle = population[gen_index(rng, 0, survivors)].puzzle.clone();
+            for _ in 0..gen_index(rng, 1, 4) {
+                mutate(rng, &mut puzzle);
+            }
+            match clean_up(&puzzle) {
+                Ok(puzzle) => {
+                    population.push(Individual::new(puzzle, settings, registry));
+                    failed = 0;
+                },
+                Err(_) => failed += 1,
+            }
+        }
+    }
+    Ok(())
+}
+
+// Moves one wall, crate, goal or the player to a random square inside the level. Pieces are only put down on squares
+// the player can walk to, & only walls with the level on every open side are moved, so the enclosure stays in place.
+fn mutate(rng: &mut ChaCha8Rng, puzzle: &mut TileMatrix) {
+    let width = puzzle.width;
+    let height = puzzle.data.len() / width;
+    let walkable = util::find_walkable_spaces(puzzle);
+    let is_walkable = |p: Point2D| walkable.get(p).unwrap();
+    let is_inner_wall = |p: Point2D| {
+        // squares on the edge of the map have no square past them to look at.
+        if puzzle.get(p) != Tile::Wall || p.x == 0 || p.y == 0 || p.x + 1 == width || p.y + 1 == height {
+            return false;
+        }
+        let sides = [Action::Up, Action::Down, Action::Left, Action::Right].map(|action| p.from(action));
+        sides.iter().all(|side| is_walkable(*side) || puzzle.get(*side) == Tile::Wall)
+            && sides.iter().any(|side| is_walkable(*side))
+    };
+    let inner: Vec<Point2D> = (0..puzzle.data.len()).map(|i| Point2D::new(i % width, i / width))
+        .filter(|p| is_walkable(*p) || is_inner_wall(*p)).collect();
+    let pick = |rng: &mut ChaCha8Rng, puzzle: &TileMatrix, tiles: &[Tile]| -> Option<Point2D> {
+        let squares: Vec<Point2D> = inner.iter().copied().filter(|p| tiles.contains(&puzzle.get(*p))).collect();
+        choose(rng, &squares).copied()
+    };
+
+    // (tiles holding the piece, the piece, tiles it can be put down on)
+    let (from, piece, to): (&[Tile], Tile, &[Tile]) = match gen_index(rng, 0, 4) {
+        0 => (&[Tile::Wall], Tile::Wall, &[Tile::Floor]),
+        1 => (&[Tile::Crate, Tile::CrateGoal], Tile::Crate, &[Tile::Floor, Tile::Goal]),
+        2 => (&[Tile::Goal, Tile::CrateGoal, Tile::PlayerGoal], Tile::Goal, &[Tile::Floor, Tile::Crate, Tile::Player]),
+        _ => (&[Tile::Player, Tile::PlayerGoal], Tile::Player, &[Tile::Floor, Tile::Goal]),
+    };
+    let start = match pick(rng, puzzle, from) {
+        Some(p) => p,
+        None => return,
+    };
+    puzzle.set(start, lift(puzzle.get(start), piece));
+    let end = pick(rng, puzzle, to).unwrap_or(start);
+    puzzle.set(end, put_down(puzzle.get(end), piece));
+}
+
+// what is left on a square after piece is taken off it.
+fn lift(tile: Tile, piece: Tile) -> Tile {
+    match (tile, piece) {
+        (Tile::CrateGoal, Tile::Crate) | (Tile::PlayerGoal, Tile::Player) => Tile::Goal,
+        (Tile::CrateGoal, Tile::Goal) => Tile::Crate,
+        (Tile::PlayerGoal, Tile::Goal) => Tile::Player,
+        _ => Tile::Floor,
+    }
+}
+
+// a square after piece is put on it.
+fn put_down(tile: Tile, piece: Tile) -> Tile {
+    match (tile, piece) {
+        (Tile::Goal, Tile::Crate) | (Tile::Crate, Tile::Goal) => Tile::CrateGoal,
+        (Tile::Goal, Tile::Player) | (Tile::Player, Tile::Goal) => Tile::PlayerGoal,
+        _ => piece,
+    }
+}
+
+// The template with goal_num goals & wall_num walls scattered over its open floor, along with the goal positions. 
+// Fixed goals count towards goal_num.
+fn place_goals_and_walls(rng: &mut ChaCha8Rng, settings: &GenSettings, template: &Template) -> (Vec<char>, Vec<Point2D>) {
+    let mut puzzle_vec: Vec<char> = template.squares.clone();
+    let open = template.open_squares();
+
+    let mut goals: Vec<Point2D> = template.fixed_goals();
+    while goals.len() < settings.goal_num {
+        let p = *choose(rng, &open).unwrap();
+        let index = template.index(p);
+        if puzzle_vec[index] == ' ' {
+            puzzle_vec[index] = '.';
+            goals.push(p);
+        }
+    }
+
+    let mut walls_added = 0;
+    while walls_added < settings.wall_num {
+        let index = template.index(*choose(rng, &open).unwrap());
+        if puzzle_vec[index] == ' ' {
+            puzzle_vec[index] = '#';
+            walls_added += 1;
+        }
+    }
+    (puzzle_vec, goals)
+}
+
+// places the player on a random empty square or goal.
+fn place_player(rng: &mut ChaCha8Rng, template: &Template, puzzle_vec: &mut [char]) {
+    let open = template.open_squares();
+    let mut player_added = false;
+    while !player_added {
+        let index = template.index(*choose(rng, &open).unwrap());
+        if puzzle_vec[index] == ' ' {
+            puzzle_vec[index] = '@';
+            player_added = true;
+        } else if puzzle_vec[index] == '.' {
+            puzzle_vec[index] = '+';
+            player_added = true;
+        }
+    }
+}
+
+// Scatters crates on squares they can be pushed to a goal from. Many of these puzzles are still unsolvable.
+fn random_puzzle(rng: &mut ChaCha8Rng, settings: &GenSettings, template: &Template) -> Option<TileMatrix> {
+    let (mut puzzle_vec, goals) = place_goals_and_walls(rng, settings, template);
+
+    // Check all locations which can be pulled to.
+    let puzzle_string: String = puzzle_vec.iter().collect();
+    let tile_map = TileMatrix::from_string_bare(&puzzle_string[..]);
+    let good_spaces: BitMatrix = util::find_simple_deadlocks(&tile_map, &goals);
+
+    // check if there are enough spaces for the crates to go in.
+    let crate_spaces: Vec<Point2D> = template.open_squares().into_iter()
+        .filter(|p| good_spaces.get(*p).unwrap() && puzzle_vec[template.index(*p)] == ' ')
+        .collect();
+    
+    if crate_spaces.len() < goals.len() {
+        println!("Could not assign crates, retrying current puzzle...");
+        return None;
+    }
+
+    for p in choose_multiple(rng, &crate_spaces, goals.len()) {
+        puzzle_vec[template.index(p)] = '$';
+    }
+
+    place_player(rng, template, &mut puzzle_vec);
+    let puzzle_string: String = puzzle_vec.iter().collect();
+    Some(TileMatrix::from_string(&puzzle_string))
+}
+
+// Starts with every crate on a goal, then makes random pulls. Any pull can be undone by a push, so playing the pulls 
+// backwards solves the puzzle. Returns the puzzle & that solution.
+fn reverse_puzzle(rng: &mut ChaCha8Rng, settings: &GenSettings, template: &Template) -> Option<(TileMatrix, Vec<Action>)> {
+    let (mut puzzle_vec, goals) = place_goals_and_walls(rng, settings, template);
+    for goal in &goals {
+        puzzle_vec[template.index(*goal)] = '*';
+    }
+    place_player(rng, template, &mut puzzle_vec);
+    let puzzle_string: String = puzzle_vec.iter().collect();
+    let solved = TileMatrix::from_string(&puzzle_string);
+
+    let (mut crates, mut player) = util::replay_pushes(&solved, &[]).unwrap()[0].clone();
+    let mut static_map = solved.clone();
+    for tile in static_map.data.iter_mut() {
+        *tile = match tile {
+            Tile::Crate | Tile::Player => Tile::Floor,
+            Tile::CrateGoal | Tile::PlayerGoal => Tile::Goal,
+            _ => *tile,
+        }
+    }
+
+    let width = static_map.width;
+    let mut reach = util::Reachability::new(static_map.data.len());
+    let mut pushes: Vec<(Point2D, Action)> = Vec::new();  // the push that undoes each pull.
+    for _ in 0..settings.pulls {
+        // a crate can be pulled when the player can reach its side, & has room to step back.
+        reach.fill(&static_map, &crates, player);
+        let mut pulls: Vec<(Point2D, Action)> = Vec::new();
+        for crate_pos in crates.iter() {
+            for &action in &[Action::Up, Action::Down, Action::Left, Action::Right] {
+                // reachable squares are walled in, so the square past one is only looked at once it's known to be reachable.
+                let player_start = crate_pos.from(action);
+                if !reach.is_reachable(player_start, width) {
+                    continue;
+                }
+                let player_end = player_start.from(action);
+                if static_map.get(player_end) != Tile::Wall && !crates.get(player_end) {
+                    pulls.push((crate_pos, action));
+                }
+            }
+        }
+
+        let (crate_pos, action) = match choose(rng, &pulls) {
+            Some(pull) => *pull,
+            None => break,
+        };
+        let crate_end = crate_pos.from(action);
+        crates.move_crate(crate_pos, crate_end);
+        player = crate_end.from(action);
+        pushes.push((crate_end, action.inverse().to_push()));
+    }
+
+    if crates.iter().all(|c| static_map.get(c) == Tile::Goal) {
+        println!("Crates could not be pulled off the goals, retrying current puzzle...");
+        return None;
+    }
+
+    // the player can start anywhere it could walk to after the last pull.
+    reach.fill(&static_map, &crates, player);
+    let reachable: Vec<Point2D> = (0..static_map.data.len())
+        .map(|i| Point2D::new(i % width, i / width))
+        .filter(|p| reach.is_reachable(*p, width))
+        .collect();
+    player = *choose(rng, &reachable).unwrap();
+
+    let mut puzzle = static_map.clone();
+    for crate_pos in crates.iter() {
+        let tile = if static_map.get(crate_pos) == Tile::Goal { Tile::CrateGoal } else { Tile::Crate };
+        puzzle.set(crate_pos, tile);
+    }
+    let tile = if static_map.get(player) == Tile::Goal { Tile::PlayerGoal } else { Tile::Player };
+    puzzle.set(player, tile);
+
+    pushes.reverse();
+    let solution = util::walk_pushes(&mut util::PathCache::new(static_map.data.len()), &static_map, crates, player, &pushes);
+    Some((puzzle, solution))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level_reader::Collection;
+
+    // trimmed, so its walls sit on the edge of the map.
+    const LEVEL: &str = "\
+#######
+#  .  #
+# #$# #
+# $  ##
+#.@ ###
+#######
+";
+
+    #[test]
+    fn mutate_keeps_level_whole() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let count = |puzzle: &TileMatrix, tiles: &[Tile]| puzzle.data.iter().filter(|t| tiles.contains(t)).count();
+        for _ in 0..200 {
+            let mut puzzle = TileMatrix::from_string(LEVEL);
+            for _ in 0..3 {
+                mutate(&mut rng, &mut puzzle);
+            }
+            assert_eq!(puzzle.width, 7);
+            assert!(puzzle.is_enclosed());
+            assert_eq!(count(&puzzle, &[Tile::Player, Tile::PlayerGoal]), 1);
+            assert_eq!(count(&puzzle, &[Tile::Crate, Tile::CrateGoal]), 2);
+            assert_eq!(count(&puzzle, &[Tile::Goal, Tile::CrateGoal, Tile::PlayerGoal]), 2);
+        }
+    }
+
+    fn settings(mode: GenMode) -> GenSettings {
+        GenSettings {
+            width: 8, height: 8, shape: Shape::Rectangle, batch_num: 3, goal_num: 2, wall_num: 3, mode, pulls: 50,
+            solvable_only: false, dedupe: false, targets: None, heuristic: "hungarian".to_string(), time_limit: 10.0,
+            max_attempts: 200, population: 4, generations: 2, seed: None, level_seed: None,
+        }
+    }
+
+    #[test]
+    fn reverse_solutions_solve_their_puzzles() {
+        let settings = settings(GenMode::Reverse);
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let mut checked = 0;
+        for _ in 0..20 {
+            if let Some((puzzle, solution)) = reverse_puzzle(&mut rng, &settings, &Template::rectangle(8, 8)) {
+                assert!(util::verify_solution(&puzzle, &solution).is_ok());
+                // trimming the puzzle doesn't move anything relative to the player.
+                if let Ok(cleaned) = clean_up(&puzzle) {
+                    assert!(util::verify_solution(&cleaned, &solution).is_ok());
+                }
+                checked += 1;
+            }
+        }
+        assert!(checked > 0);
+    }
+
+    fn generate_string(settings: &GenSettings, seed: u64) -> String {
+        let mut file_string = String::new();
+        generate(seed, settings, &HeuristicRegistry::new(), &mut file_string);
+        file_string
+    }
+
+    #[test]
+    fn same_seed_makes_same_puzzles() {
+        let settings = settings(GenMode::Reverse);
+        let first = generate_string(&settings, 42);
+        assert!(!first.is_empty());
+        assert_eq!(first, generate_string(&settings, 42));
+    }
+
+    #[test]
+    fn level_seed_remakes_its_puzzle() {
+        let mut settings = settings(GenMode::Reverse);
+        let collection = Collection::from_string(&generate_string(&settings, 42)).unwrap();
+        let level = &collection.levels[1];
+        let level_seed = level.comment.strip_prefix("Seed: ").unwrap().parse().unwrap();
+
+        settings.level_seed = Some(level_seed);
+        let remade = Collection::from_string(&generate_string(&settings, 0)).unwrap();
+        assert_eq!(remade.levels.len(), 1);
+        assert_eq!(remade.levels[0].puzzle.to_string(), level.puzzle.to_string());
+    }
+
+    #[test]
+    fn clean_up_walls_in_and_trims() {
+        // (5, 2) is floor the player can't reach, & the outer walls don't border any walkable square.
+        let puzzle = TileMatrix::from_string("########\n########\n##  # ##\n##@$.###\n########\n########\n");
+        let cleaned = clean_up(&puzzle).unwrap();
+        // the wall at the top right corner has no walkable square around it, so it becomes outside.
+        assert_eq!(cleaned.width, 5);
+        assert_eq!(cleaned.to_string(), "####\n#  ##\n#@$.#\n#####\n");
+    }
+
+    #[test]
+    fn clean_up_rejects_solved_puzzles() {
+        let puzzle = TileMatrix::from_string("######\n#@ * #\n######\n");
+        assert_eq!(clean_up(&puzzle).err(), Some("Every crate starts on a goal"));
+    }
+}
@@ -1,10 +1,16 @@
 use std::fs;
+use std::fmt;
 use std::process;
 
 use std::path::Path;
 use std::ffi::OsStr;
 
-use crate::types::{TileMatrix, Tile};
+use quick_xml::Reader;
+use quick_xml::events::Event;
+use quick_xml::escape::{escape, resolve_predefined_entity};
+
+use crate::types::{TileMatrix, Action};
+use crate::util;
 
 pub fn get_extension_from_filename(filename: &str) -> Option<&str> {
     Path::new(filename)
@@ -12,128 +18,382 @@ pub fn get_extension_from_filename(filename: &str) -> Option<&str> {
         .and_then(OsStr::to_str)
 }
 
-fn puzzle_string_to_puzzle(puzzle_string: &str) -> TileMatrix {
-    match puzzle_string.find('\n') {
-        Some(v) => v,
-        None => {
-            println!("Error: puzzle file is malformed.\nreason: must include newline.");
+// reads puzzle from the given location and returns a visualizable puzzle matrix.
+// Any errors which are encountered automatically exit and give user-bound error message.
+pub fn read_puzzle(filepath: &str, print_puzzle: bool) -> TileMatrix {
+    let puzzle_string = match fs::read_to_string(filepath) {
+        Ok(puzzle) => puzzle,
+        Err(_) => {
+            println!("Error: Input path does not exist, or cannot be opened.");
             process::exit(1);
         },
     };
+    
+    let puzzle: TileMatrix = TileMatrix::from_string(&puzzle_string[..]);
+
+    if print_puzzle {
+        println!("Successfully loaded the following puzzle:");
+        puzzle.print();
+    }
+    puzzle
+}
+
+// A .sok file, with its header & every level.
+#[derive(Clone, Default)]
+pub struct Collection {
+    pub set: Option<String>,
+    pub copyright: Option<String>,
+    pub email: Option<String>,
+    pub homepage: Option<String>,
+    pub text: String,  // the rest of the header, kept as it was written.
+    pub levels: Vec<Level>,
+}
+
+#[derive(Clone)]
+pub struct Level {
+    pub title: String,  // the line above the puzzle, or its number when there isn't one.
+    pub author: Option<String>,
+    pub comment: String,  // any lines under the puzzle which aren't another field.
+    pub solutions: Vec<Solution>,
+    pub puzzle: TileMatrix,
+}
+
+#[derive(Clone)]
+pub struct Solution {
+    pub label: String,  // what comes before the ':', ex: "Solution" or "Solution (moves)".
+    pub lurd: String,
+}
 
-    // find puzzle width.
-    let mut puzzle_width: usize = 0;
-    let mut beg_pos: usize = 0;
-    loop {
-        match puzzle_string[beg_pos..].find('\n') {
-            Some(v) => {
-                beg_pos += v + 1;
-                if v > puzzle_width {
-                    puzzle_width = v;
+impl Collection {
+    // Levels are found by their puzzle rows. The last line above a puzzle is its title, even when it looks like lurd, 
+    // the lines under it are its fields & comment, & everything before the first title is the header. A malformed 
+    // puzzle is an error naming its level.
+    pub fn from_string(file_string: &str) -> Result<Collection, String> {
+        let lines: Vec<&str> = file_string.lines().map(|line| line.trim_end_matches('\r')).collect();
+        let mut boards: Vec<(usize, usize)> = Vec::new();
+        let mut i = 0;
+        while i < lines.len() {
+            if is_puzzle_row(lines[i]) {
+                let start = i;
+                while i < lines.len() && is_puzzle_row(lines[i]) {
+                    i += 1;
                 }
-            },
-            None => break,
-        };
-        if beg_pos > puzzle_string.len() { 
-            break; 
+                boards.push((start, i));
+            } else {
+                i += 1;
+            }
+        }
+
+        let mut collection = Collection::default();
+        let mut previous_end = 0;
+        for (n, (start, end)) in boards.into_iter().enumerate() {
+            let title_line = (previous_end..start).rev()
+                .find(|i| !lines[*i].trim().is_empty())
+                .filter(|i| !is_field(lines[*i]));
+            let fields_end = title_line.unwrap_or(start);
+            if n == 0 {
+                collection.read_header(&lines[..fields_end]);
+            } else {
+                collection.levels[n-1].read_fields(&lines[previous_end..fields_end]);
+            }
+
+            let puzzle_string: String = lines[start..end].iter()
+                .map(|line| format!("{}\n", line.replace(['-', '_'], " ")))
+                .collect();
+            let title = title_line.map(|i| lines[i].trim().to_string()).unwrap_or_else(|| (n+1).to_string());
+            let puzzle = TileMatrix::try_from_string(&puzzle_string)
+                .map_err(|reason| format!("level {} \"{}\": {}", n+1, title, reason))?;
+            collection.levels.push(Level::new(title, puzzle));
+            previous_end = end;
         }
+        if let Some(level) = collection.levels.last_mut() {
+            level.read_fields(&lines[previous_end..]);
+        }
+        Ok(collection)
     }
 
-    if puzzle_width == 0 {
-        println!("Error: puzzle file is malformed.\nreason: newline cannot be first character.");
-        process::exit(1);
+    // Reads a SokobanLevels XML collection. Its Title, Description, Email, Url & Copyright map onto the .sok header, 
+    // & each Level's Id & Copyright onto the level's title & author.
+    pub fn from_slc(xml: &str) -> Result<Collection, String> {
+        let mut reader = Reader::from_str(xml);
+        let mut collection = Collection::default();
+        let mut text = String::new();  // text of the element being read.
+        let mut rows = String::new();
+        let mut level: Option<Level> = None;
+        loop {
+            let event = reader.read_event()
+                .map_err(|err| format!("{} at byte {}", err, reader.error_position()))?;
+            match event {
+                // an empty <L/> is a blank row, which has to be kept so the rows under it stay in place.
+                Event::Empty(e) if e.name().as_ref() == b"L" => rows.push('\n'),
+                Event::Start(e) | Event::Empty(e) => {
+                    text.clear();
+                    let attribute = |name: &[u8]| -> Result<Option<String>, String> {
+                        for a in e.attributes() {
+                            let a = a.map_err(|err| err.to_string())?;
+                            if a.key.as_ref() == name {
+                                let value = a.decode_and_unescape_value(reader.decoder()).map_err(|err| err.to_string())?;
+                                return Ok(Some(value.into_owned()));
+                            }
+                        }
+                        Ok(None)
+                    };
+                    match e.name().as_ref() {
+                        b"LevelCollection" => collection.copyright = attribute(b"Copyright")?,
+                        b"Level" => {
+                            let title = attribute(b"Id")?.unwrap_or_else(|| (collection.levels.len() + 1).to_string());
+                            let mut new_level = Level::new(title, TileMatrix { width: 0, data: Vec::new() });
+                            new_level.author = attribute(b"Copyright")?;
+                            level = Some(new_level);
+                            rows.clear();
+                        },
+                        _ => (),
+                    }
+                },
+                Event::Text(e) => text.push_str(&e.decode().map_err(|err| err.to_string())?),
+                Event::CData(e) => text.push_str(&e.decode().map_err(|err| err.to_string())?),
+                Event::GeneralRef(e) => {
+                    // entities like &amp; come separately from the text around them.
+                    let name = e.decode().map_err(|err| err.to_string())?;
+                    match e.resolve_char_ref().map_err(|err| err.to_string())? {
+                        Some(ch) => text.push(ch),
+                        None => match resolve_predefined_entity(&name) {
+                            Some(resolved) => text.push_str(resolved),
+                            None => return Err(format!("unknown entity &{};", name)),
+                        },
+                    }
+                },
+                Event::End(e) => {
+                    match e.name().as_ref() {
+                        b"Title" => collection.set = Some(text.trim().to_string()),
+                        b"Description" => collection.text = text.trim().to_string(),
+                        b"Email" => collection.email = Some(text.trim().to_string()),
+                        b"Url" => collection.homepage = Some(text.trim().to_string()),
+                        b"L" => rows.push_str(&format!("{}\n", text.replace(['-', '_'], " "))),
+                        b"Level" => if let Some(mut finished) = level.take() {
+                            finished.puzzle = TileMatrix::try_from_string(&rows).map_err(|reason| 
+                                format!("level {} \"{}\": {}", collection.levels.len() + 1, finished.title, reason))?;
+                            collection.levels.push(finished);
+                        },
+                        _ => (),
+                    }
+                    text.clear();
+                },
+                Event::Eof => break,
+                _ => (),
+            }
+        }
+        Ok(collection)
     }
 
-    let mut player_count = 0;
-    let mut goal_count = 0;
-    let mut crate_count = 0;
-
-    // map string to Tile enum.
-    let mut line_number = 0;
-    let mut index = 0;
-    let mut tile_vec: Vec<Tile> = Vec::new();
-    for ch in puzzle_string.chars() {
-        match ch {
-            '#' => tile_vec.push(Tile::Wall),
-            '@' => {
-                tile_vec.push(Tile::Player); 
-                player_count += 1; 
-            },
-            '+' => {
-                tile_vec.push(Tile::PlayerGoal); 
-                player_count += 1; 
-                goal_count += 1;
-            },
-            '$' => {
-                tile_vec.push(Tile::Crate);
-                crate_count += 1;
-            },
-            '*' => {
-                tile_vec.push(Tile::CrateGoal);
-                goal_count += 1;
-                crate_count += 1;
-            },
-            '.' => {
-                tile_vec.push(Tile::Goal); 
-                goal_count += 1;
-            },
-            ' ' => tile_vec.push(Tile::Floor),
-            '\n' => {
-                // add extra padding to the map.
-                line_number += 1;
-                for _ in index..(line_number * puzzle_width) {
-                    tile_vec.push(Tile::Floor);
-                }
-                index = line_number * puzzle_width;
-                index -= 1;
-            },
-            '\r' => index -= 1,
-            _ => {
-                println!("Error: puzzle file is malformed.\nreason: invalid character in puzzle file, \"{}\".\npuzzle can only contain the characters \"#@+$*. \"", ch);
-                process::exit(1);
-            },
-        };
-        index += 1;
+    // The collection as SokobanLevels XML. The format has no place for level comments or solutions, so they're left out.
+    pub fn to_slc(&self) -> String {
+        let xml_escape = |s: &str| escape(s).into_owned();
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+            <SokobanLevels xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xsi:schemaLocation=\"SokobanLev.xsd\">\n");
+        if let Some(set) = &self.set {
+            xml.push_str(&format!("  <Title>{}</Title>\n", xml_escape(set)));
+        }
+        xml.push_str(&format!("  <Description>\n{}\n  </Description>\n", xml_escape(&self.text)));
+        for (tag, value) in [("Email", &self.email), ("Url", &self.homepage)] {
+            if let Some(value) = value {
+                xml.push_str(&format!("  <{0}>{1}</{0}>\n", tag, xml_escape(value)));
+            }
+        }
+
+        let rows: Vec<Vec<String>> = self.levels.iter()
+            .map(|level| level.puzzle.to_string().lines().map(String::from).collect())
+            .collect();
+        let max_width = rows.iter().flatten().map(|row| row.chars().count()).max().unwrap_or(0);
+        let max_height = rows.iter().map(|level_rows| level_rows.len()).max().unwrap_or(0);
+        xml.push_str(&format!("  <LevelCollection Copyright=\"{}\" MaxWidth=\"{}\" MaxHeight=\"{}\">\n",
+                              xml_escape(self.copyright.as_deref().unwrap_or("")), max_width, max_height));
+        for (level, level_rows) in self.levels.iter().zip(&rows) {
+            let width = level_rows.iter().map(|row| row.chars().count()).max().unwrap_or(0);
+            xml.push_str(&format!("    <Level Id=\"{}\" Width=\"{}\" Height=\"{}\"", xml_escape(&level.title), width, level_rows.len()));
+            if let Some(author) = &level.author {
+                xml.push_str(&format!(" Copyright=\"{}\"", xml_escape(author)));
+            }
+            xml.push_str(">\n");
+            for row in level_rows {
+                xml.push_str(&format!("      <L>{}</L>\n", row));
+            }
+            xml.push_str("    </Level>\n");
+        }
+        xml.push_str("  </LevelCollection>\n</SokobanLevels>\n");
+        xml
     }
 
-    if player_count != 1 {
-        println!("Error: puzzle file is malformed.\nreason: There must be exactly 1 player tile, \"@\".");
-        process::exit(1);
+    fn read_header(&mut self, lines: &[&str]) {
+        let mut text: Vec<&str> = Vec::new();
+        for line in lines {
+            match split_field(line) {
+                Some(("set", value)) => self.set = Some(value),
+                Some(("copyright", value)) => self.copyright = Some(value),
+                Some(("email", value)) => self.email = Some(value),
+                Some(("homepage", value)) => self.homepage = Some(value),
+                _ if !line.is_empty() && line.chars().all(|ch| ch == ':') => (),  // old style header markers.
+                // fields are taken out of the text, so the blank lines around them are merged.
+                _ if line.trim().is_empty() && text.last().is_some_and(|last| last.trim().is_empty()) => (),
+                _ => text.push(line),
+            }
+        }
+        self.text = join_trimmed(&text);
     }
+}
 
-    if crate_count != goal_count {
-        println!("Error: puzzle file is malformed.\nreason: There must be the same number of goals and crates.");
-        process::exit(1);
+impl Level {
+    pub fn new(title: String, puzzle: TileMatrix) -> Level {
+        Level { title, author: None, comment: String::new(), solutions: Vec::new(), puzzle }
+    }
+
+    // Stores a solution, labelled with its pushes, moves & how it was found, if it has fewer (pushes, moves) than every 
+    // valid stored solution. Returns whether it was added.
+    pub fn add_solution(&mut self, actions: &[Action], found_by: &str) -> bool {
+        let (pushes, moves) = match util::verify_solution(&self.puzzle, actions) {
+            Ok(score) => score,
+            Err(_) => return false,
+        };
+        let is_better = self.solutions.iter()
+            .filter_map(|solution| Action::from_string(&solution.lurd))
+            .filter_map(|stored| util::verify_solution(&self.puzzle, &stored).ok())
+            .all(|stored_score| (pushes, moves) < stored_score);
+        if is_better {
+            self.solutions.push(Solution {
+                label: format!("Solution (pushes {}, moves {}, {})", pushes, moves, found_by),
+                lurd: Action::to_string(&actions.to_vec()),
+            });
+        }
+        is_better
+    }
+
+    fn read_fields(&mut self, lines: &[&str]) {
+        let mut comment: Vec<&str> = Vec::new();
+        let mut i = 0;
+        while i < lines.len() {
+            let line = lines[i];
+            i += 1;
+            match split_field(line) {
+                Some(("title", value)) => self.title = value,
+                Some(("author", value)) => self.author = Some(value),
+                Some(("comment", value)) if value.is_empty() => {
+                    // a comment block runs until its end marker.
+                    while i < lines.len() && split_field(lines[i]).map(|(k, _)| k) != Some("comment-end") {
+                        comment.push(lines[i]);
+                        i += 1;
+                    }
+                    i += 1;
+                },
+                Some(("comment", _)) => comment.push(line.split_once(':').unwrap().1.trim()),
+                Some(("solution", value)) => {
+                    // a solution carries on over the lurd lines right under it, up to the first blank line.
+                    let mut lurd = value;
+                    while i < lines.len() && is_lurd(lines[i]) {
+                        lurd.push_str(lines[i].trim());
+                        i += 1;
+                    }
+                    let label = line.split(':').next().unwrap().trim().to_string();
+                    self.solutions.push(Solution { label, lurd });
+                },
+                _ => comment.push(line),
+            }
+        }
+        self.comment = join_trimmed(&comment);
     }
+}
 
-    TileMatrix {
-        width: puzzle_width, 
-        data: tile_vec,
+// the collection in .sok format.
+impl fmt::Display for Collection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fields = [("Set", &self.set), ("Copyright", &self.copyright), ("Email", &self.email), ("Homepage", &self.homepage)];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                writeln!(f, "{}", format!("{}: {}", key, value).trim_end())?;
+            }
+        }
+        if fields.iter().any(|(_, value)| value.is_some()) {
+            writeln!(f)?;
+        }
+        if !self.text.is_empty() {
+            writeln!(f, "{}\n", self.text)?;
+        }
+        for level in &self.levels {
+            write!(f, "{}\n{}", level.title, level.puzzle)?;
+            if let Some(author) = &level.author {
+                writeln!(f, "Author: {}", author)?;
+            }
+            if !level.comment.is_empty() {
+                writeln!(f, "{}", level.comment)?;
+            }
+            for solution in &level.solutions {
+                writeln!(f, "{}: {}", solution.label, solution.lurd)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
     }
 }
 
-// reads puzzle from the given location and returns a visualizable puzzle matrix.
-// Any errors which are encountered automatically exit and give user-bound error message.
-pub fn read_puzzle(filepath: &str, print_puzzle: bool) -> TileMatrix {
-    let puzzle_string = match fs::read_to_string(filepath) {
+fn is_puzzle_row(line: &str) -> bool {
+    line.contains('#') && line.chars().all(|ch| "#@+$*. -_".contains(ch))
+}
+
+fn is_lurd(line: &str) -> bool {
+    !line.trim().is_empty() && line.trim().chars().all(|ch| "lurdLURD".contains(ch))
+}
+
+// (lowercase key, value) of a "Key: value" line, for the keys this reader knows about.
+fn split_field(line: &str) -> Option<(&'static str, String)> {
+    let (key, value) = line.split_once(':')?;
+    let key = key.trim().to_lowercase();
+    let known = ["set", "copyright", "email", "homepage", "title", "author", "comment", "comment-end"];
+    let key = match known.iter().find(|k| **k == key) {
+        Some(k) => *k,
+        None if key.starts_with("solution") => "solution",
+        None => return None,
+    };
+    Some((key, value.trim().to_string()))
+}
+
+fn is_field(line: &str) -> bool {
+    split_field(line).is_some()
+}
+
+fn join_trimmed(lines: &[&str]) -> String {
+    lines.join("\n").trim_matches('\n').to_string()
+}
+
+// Loads every level in a .sok file, along with its metadata.
+pub fn read_collection(filepath: &str, verbose: bool) -> Collection {
+    let file_string = match fs::read_to_string(filepath) {
         Ok(puzzle) => puzzle,
         Err(_) => {
             println!("Error: Input path does not exist, or cannot be opened.");
             process::exit(1);
         },
     };
-    
-    let puzzle: TileMatrix = TileMatrix::from_string(&puzzle_string[..]);
 
-    if print_puzzle {
-        println!("Successfully loaded the following puzzle:");
-        puzzle.print();
+    let collection = match Collection::from_string(&file_string) {
+        Ok(collection) => collection,
+        Err(reason) => {
+            println!("Error: .sok file is malformed.\nreason: {}", reason);
+            process::exit(1);
+        },
+    };
+    if collection.levels.is_empty() {
+        println!("Error: No puzzles were found in the supplied .sok file.");
+        println!("Make sure each puzzle is written with # walls, under a title line.");
+        process::exit(1);
     }
-    puzzle
+
+    if verbose {
+        println!("Successfully loaded {} sokoban puzzles.", collection.levels.len());
+    }
+    collection
 }
 
-const HEADER_START: &str = "::::::::::::::::::::::::::::::::::::::::::::::::::::::::::::::::::::::";
-pub fn read_sok(filepath: &str, verbose: bool) -> Vec<TileMatrix> {
+// Loads every level in a .slc file.
+pub fn read_slc(filepath: &str, verbose: bool) -> Collection {
     let file_string = match fs::read_to_string(filepath) {
         Ok(puzzle) => puzzle,
         Err(_) => {
@@ -142,72 +402,217 @@ pub fn read_sok(filepath: &str, verbose: bool) -> Vec<TileMatrix> {
         },
     };
 
-    let mut puzzles: Vec<TileMatrix> = Vec::new();
-    let mut current_puzzle_string = String::new();
-    let mut current_number: usize = 1;
-    let mut state = "looking_for_header";
-    for line in file_string.lines() {
-        match state {
-            "looking_for_header" => {
-                if line == HEADER_START {
-                    state = "looking_for_header_end";
-                } else {
-                    // case: no header.
-                    match line.parse::<usize>() {
-                        Ok(num) => {
-                            if num == current_number {
-                                state = "saving_puzzle";
-                            }
-                        },
-                        Err(_) => (),
-                    }
-                }
-            },
-            "looking_for_header_end" => {
-                if line == HEADER_START {
-                    state = "looking_for_puzzle_number";
-                }
-            },
-            "looking_for_puzzle_number" => {
-                match line.parse::<usize>() {
-                    Ok(num) => {
-                        if num == current_number {
-                            state = "saving_puzzle";
-                        }
-                    },
-                    Err(_) => (),
-                }
-            },
-            "saving_puzzle" => {
-                // check if the line starts with invalid characters (must always be "Title")
-                if line.len() != 0 && "# @$.+*".contains(line.chars().nth(0).unwrap()) {  
-                    current_puzzle_string.push_str( &format!("{}\n", line) );
-                } else {  // case: invalid line -> current puzzle is over.
-                    let cur_puzzle: TileMatrix = TileMatrix::from_string(&current_puzzle_string[..]);
-                    puzzles.push(cur_puzzle);
-
-                    state = "looking_for_puzzle_number";
-                    current_number += 1;
-                    current_puzzle_string = String::new();
-                }
-            },
-            _ => (),
-        }
+    let collection = match Collection::from_slc(&file_string) {
+        Ok(collection) => collection,
+        Err(reason) => {
+            println!("Error: .slc file is malformed.\nreason: {}", reason);
+            process::exit(1);
+        },
+    };
+    if collection.levels.is_empty() {
+        println!("Error: No puzzles were found in the supplied .slc file.");
+        process::exit(1);
     }
 
-    if state == "saving_puzzle" {
-        let cur_puzzle: TileMatrix = puzzle_string_to_puzzle(&current_puzzle_string[..]);
-        puzzles.push(cur_puzzle);
+    if verbose {
+        println!("Successfully loaded {} sokoban puzzles.", collection.levels.len());
     }
+    collection
+}
 
-    if puzzles.len() == 0 {
-        println!("Error: No puzzles were found in the supplied .sok file.");
-        println!("Make sure it is formatted properly with a header & numbered puzzles starting from 1.");
+// Loads a .sok or .slc collection by its extension. Any other file -- like .txt -- is read as a single puzzle.
+pub fn read_levels(filepath: &str, verbose: bool) -> Collection {
+    match get_extension_from_filename(filepath) {
+        Some("sok") => read_collection(filepath, verbose),
+        Some("slc") => read_slc(filepath, verbose),
+        _ => {
+            let puzzle = read_puzzle(filepath, verbose);
+            Collection { levels: vec![Level::new("1".to_string(), puzzle)], ..Collection::default() }
+        },
+    }
+}
+
+// Writes the collection as .slc when the file has that extension, & as .sok otherwise.
+pub fn write_collection(filepath: &str, collection: &Collection) {
+    let file_string = match get_extension_from_filename(filepath) {
+        Some("slc") => collection.to_slc(),
+        _ => collection.to_string(),
+    };
+    if fs::write(filepath, file_string).is_err() {
+        println!("Error: file unable to be written.");
         process::exit(1);
     }
+}
 
-    if verbose {
-        println!("Successfully loaded {} sokoban puzzles.", puzzles.len());
+// the puzzles of a .sok file, without their metadata.
+pub fn read_sok(filepath: &str, verbose: bool) -> Vec<TileMatrix> {
+    read_collection(filepath, verbose).levels.into_iter().map(|level| level.puzzle).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOK: &str = "\
+Set: Test Set
+Copyright: Someone & Co
+Email: someone@example.com
+Homepage: example.com
+
+A couple of small levels.
+
+First
+#####
+#@$.#
+#####
+Author: Someone
+An easy one.
+Solution (pushes 1, moves 1): R
+
+DR
+######
+#@$ .#
+######
+Solution: RR
+R
+Also a comment.
+
+Rudd
+#####
+#@$.#
+#####
+";
+
+    #[test]
+    fn sok_fields_are_read() {
+        let collection = Collection::from_string(SOK).unwrap();
+        assert_eq!(collection.set.as_deref(), Some("Test Set"));
+        assert_eq!(collection.copyright.as_deref(), Some("Someone & Co"));
+        assert_eq!(collection.email.as_deref(), Some("someone@example.com"));
+        assert_eq!(collection.homepage.as_deref(), Some("example.com"));
+        assert_eq!(collection.text, "A couple of small levels.");
+
+        let titles: Vec<&str> = collection.levels.iter().map(|level| level.title.as_str()).collect();
+        assert_eq!(titles, ["First", "DR", "Rudd"]);
+        let first = &collection.levels[0];
+        assert_eq!(first.author.as_deref(), Some("Someone"));
+        assert_eq!(first.comment, "An easy one.");
+        assert_eq!(first.solutions.len(), 1);
+        assert_eq!(first.solutions[0].label, "Solution (pushes 1, moves 1)");
+        assert_eq!(first.solutions[0].lurd, "R");
+
+        // the solution runs over two lines, but stops at the comment & doesn't take the next level's title.
+        let second = &collection.levels[1];
+        assert_eq!(second.solutions.len(), 1);
+        assert_eq!(second.solutions[0].lurd, "RRR");
+        assert_eq!(second.comment, "Also a comment.");
+        assert!(collection.levels[2].solutions.is_empty());
+    }
+
+    #[test]
+    fn sok_round_trip() {
+        let collection = Collection::from_string(SOK).unwrap();
+        let written = collection.to_string();
+        let reread = Collection::from_string(&written).unwrap();
+        assert_eq!(reread.to_string(), written);
+        assert_eq!(reread.levels.len(), 3);
+        for (a, b) in collection.levels.iter().zip(&reread.levels) {
+            assert_eq!(a.title, b.title);
+            assert_eq!(a.author, b.author);
+            assert_eq!(a.comment, b.comment);
+            assert_eq!(a.puzzle.to_string(), b.puzzle.to_string());
+            let lurds = |level: &Level| level.solutions.iter().map(|s| (s.label.clone(), s.lurd.clone())).collect::<Vec<_>>();
+            assert_eq!(lurds(a), lurds(b));
+        }
+    }
+
+    #[test]
+    fn slc_round_trip() {
+        let mut collection = Collection::from_string(SOK).unwrap();
+        collection.levels[1].title = "<Two> & \"Three\"".to_string();
+        collection.levels[2].author = Some("Me & 'You'".to_string());
+        let reread = Collection::from_slc(&collection.to_slc()).unwrap();
+        assert_eq!(reread.set, collection.set);
+        assert_eq!(reread.copyright.as_deref(), Some("Someone & Co"));
+        assert_eq!(reread.email, collection.email);
+        assert_eq!(reread.homepage, collection.homepage);
+        assert_eq!(reread.text, collection.text);
+        assert_eq!(reread.levels.len(), 3);
+        for (a, b) in collection.levels.iter().zip(&reread.levels) {
+            assert_eq!(a.title, b.title);
+            assert_eq!(a.author, b.author);
+            assert_eq!(a.puzzle.to_string(), b.puzzle.to_string());
+        }
+    }
+
+    #[test]
+    fn slc_cdata_and_entities() {
+        let xml = "\
+<?xml version=\"1.0\" encoding=\"utf-8\"?>
+<SokobanLevels>
+  <Title>Rock &amp; Roll</Title>
+  <Description><![CDATA[Some <b>bold</b> text]]> &#38; more</Description>
+  <LevelCollection Copyright=\"A &amp; B\">
+    <Level Id=\"One\">
+      <L>#####</L>
+      <L><![CDATA[#@$.#]]></L>
+      <L>#####</L>
+    </Level>
+    <Level Copyright=\"C\">
+      <L>######</L>
+      <L>#@$-.#</L>
+      <L>######</L>
+    </Level>
+  </LevelCollection>
+</SokobanLevels>
+";
+        let collection = Collection::from_slc(xml).unwrap();
+        assert_eq!(collection.set.as_deref(), Some("Rock & Roll"));
+        assert_eq!(collection.text, "Some <b>bold</b> text & more");
+        assert_eq!(collection.copyright.as_deref(), Some("A & B"));
+        assert_eq!(collection.levels.len(), 2);
+        assert_eq!(collection.levels[0].title, "One");
+        assert_eq!(collection.levels[0].puzzle.to_string(), "#####\n#@$.#\n#####\n");
+        assert_eq!(collection.levels[1].title, "2");
+        assert_eq!(collection.levels[1].author.as_deref(), Some("C"));
+        assert_eq!(collection.levels[1].puzzle.to_string(), "######\n#@$ .#\n######\n");
+    }
+
+    #[test]
+    fn slc_bad_level_is_an_error() {
+        let xml = "<SokobanLevels><LevelCollection><Level Id=\"Open\"><L>#@$.</L></Level></LevelCollection></SokobanLevels>";
+        let err = Collection::from_slc(xml).err().unwrap();
+        assert!(err.starts_with("level 1 \"Open\""), "{}", err);
+    }
+
+    #[test]
+    fn sok_bad_level_is_an_error() {
+        let sok = SOK.replace("#@$ .#", "#@$$.#");
+        let err = Collection::from_string(&sok).err().unwrap();
+        assert!(err.starts_with("level 2 \"DR\""), "{}", err);
+    }
+
+    #[test]
+    fn solution_only_added_when_strictly_better() {
+        let mut collection = Collection::from_string(SOK).unwrap();
+        let first = &mut collection.levels[0];
+        // ties & longer solutions lose to the stored "R".
+        assert!(!first.add_solution(&Action::from_string("R").unwrap(), "test"));
+        assert!(!first.add_solution(&Action::from_string("lrR").unwrap(), "test"));
+        // neither does something which isn't a solution.
+        assert!(!first.add_solution(&Action::from_string("l").unwrap(), "test"));
+        assert_eq!(first.solutions.len(), 1);
+    }
+
+    #[test]
+    fn invalid_stored_solution_is_ignored() {
+        let mut collection = Collection::from_string(SOK).unwrap();
+        // the stored "RRR" pushes the crate into the wall.
+        let second = &mut collection.levels[1];
+        assert!(second.add_solution(&Action::from_string("RR").unwrap(), "test"));
+        assert_eq!(second.solutions.len(), 2);
+        assert_eq!(second.solutions[1].label, "Solution (pushes 2, moves 2, test)");
+        assert_eq!(second.solutions[1].lurd, "RR");
+        assert!(!second.add_solution(&Action::from_string("RR").unwrap(), "test"));
     }
-    puzzles
 }
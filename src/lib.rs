@@ -0,0 +1,8 @@
+// rustsoko as a library, so that other crates can drive the solver or plug in their own heuristics.
+pub mod util;
+pub mod types;
+pub mod heuristic;
+pub mod level_reader;
+pub mod ida_star_solver;
+pub mod level_generator;
+pub mod optimizer;
@@ -1,35 +1,41 @@
 use std::fs::File;
 use std::process;
+use std::collections::HashMap;
+use std::time::Instant;
 
 extern crate clap;
 use clap::{Arg, ArgGroup, App, SubCommand, AppSettings, ArgMatches};
 
-mod util;
-mod types;
-mod level_reader;
-mod ida_star_solver;
-mod level_generator;
-
-use ida_star_solver::{IDAStarSolver, heuristic};
-use types::{TileMatrix};
+use rustsoko::{level_reader, level_generator, util};
+use rustsoko::level_reader::Collection;
+use rustsoko::level_generator::{GenMode, GenSettings, DifficultyTargets, Shape, Template};
+use rustsoko::ida_star_solver::{IDAStarSolver, SearchEnd, DistinctSolutions, TIME_LIMIT};
+use rustsoko::optimizer::Optimizer;
+use rustsoko::heuristic::{Heuristic, HeuristicRegistry};
+use rustsoko::types::{TileMatrix, Action};
 
 fn main() {
+    let registry = HeuristicRegistry::new();
+    let heuristic_names = registry.names();
+
     // init cli input method
     let matches = App::new("CMPT 310 Sokoban Solver -- rustsoko")
         .version("1.0")
         .author("EarthenSky - Geb")
         .about("Implements various push optimal solving methods for sokoban puzzle.\nCan read individual puzzles & .sok files.\n\n \
-                Type \"rustsoko solve --help\" or \"rustsoko puzzle-gen --help\" for more information on subcommands.")
+                Type \"rustsoko solve --help\" or \"rustsoko puzzle-gen --help\" for more information on subcommands.\n \
+                Type \"rustsoko heuristics\" to list the available heuristics.")
         .setting(AppSettings::SubcommandRequired)
         .setting(AppSettings::UnifiedHelpMessage)
         .setting(AppSettings::VersionlessSubcommands)
         .arg(Arg::with_name("silent")
             .short("s")
             .long("silent")
-            .help("Returns puzzle stats in csv format -> time_elapsed,nodes_checked,solutions,pushes,moves,solution_string"))
+            .help("Returns puzzle stats in csv format -> time_elapsed,nodes_checked,solutions,pushes,moves,solution_string,push_optimal,move_optimal. \
+                   move_optimal is false when the time limit ran out before every solution at the final bound was compared."))
         .subcommand(
             SubCommand::with_name("solve")
-            .about("Uses IDA* to do a tree search on the problem. Puzzles will be returned 'unsolved' if they take more than 300s and don't find a solution.")
+            .about("Uses IDA* to do a tree search on the problem. Puzzles will be returned 'unsolved' if they take more than 300s (or --time-limit) and don't find a solution.")
             .arg(Arg::with_name("INPUT")
                 .required(true)
                 .index(1)
@@ -41,24 +47,148 @@ fn main() {
             .arg(Arg::with_name("deadlock-hashing")
                 .long("deadlock-hashing")
                 .help("Hashes deadlocked positions so that IDA* search can ignore the children deadlocked positions after secondary iterations."))
+            .arg(Arg::with_name("goal-room-macros")
+                .long("goal-room-macros")
+                .help("Finds a goal room with a single entrance, then fills its goals in a precomputed order, pushing each crate from the entrance to its goal in one step. \
+                       Solutions found this way may not be push optimal."))
+            .arg(Arg::with_name("all-optimal")
+                .long("all-optimal")
+                .help("Lists every distinct push optimal solution. Solutions which only differ in walking, or in the order of pushes on \
+                       different crates, count as one. With --silent, prints \"all_optimal,count,capped\" then \"pushes,moves,solution_string\" for each."))
+            .arg(Arg::with_name("max-solutions")
+                .long("max-solutions")
+                .takes_value(true)
+                .value_name("N")
+                .requires("all-optimal")
+                .help("Lists at most N distinct solutions with --all-optimal [default: 100]"))
+            .arg(Arg::with_name("bound-only")
+                .long("bound-only")
+                .conflicts_with("all-optimal")
+                .help("Reports the starting estimate of every heuristic, & the highest lower bound on pushes which IDA* proves \
+                       within the time limit, without building a solution. The search uses --heuristic [default: hungarian]. \
+                       Building the heuristics counts against the time limit, & any not built before it runs out are skipped. \
+                       With --silent, prints \"bound,level,heuristic,status,lower_bound,proven,time_elapsed,nodes_checked\" \
+                       then \"h,level,name,estimate,admissible\" for each heuristic, where level counts from 1 through the file, \
+                       & estimate is skipped if it wasn't built, or unsolvable if the heuristic found that the start can't be \
+                       solved. status is one of solved, unsolvable or time_limit."))
+            .arg(Arg::with_name("time-limit")
+                .long("time-limit")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("How long the search runs before giving up [default: 300]"))
+            .arg(Arg::with_name("write-solutions")
+                .long("write-solutions")
+                .takes_value(true)
+                .value_name("OUT.sok")
+                .conflicts_with("bound-only")
+                .help("Writes the levels to OUT.sok with each solution found, labelled with its pushes, moves & \
+                       the solver settings. The levels' metadata & stored solutions are kept, & a solution is only added \
+                       when it has fewer pushes, then moves, than every stored one."))
+            .arg(Arg::with_name("heuristic")
+                .long("heuristic")
+                .takes_value(true)
+                .value_name("NAME")
+                .possible_values(&heuristic_names)
+                .help("The heuristic used to estimate the pushes left. See \"rustsoko heuristics\" for descriptions."))
             .arg(Arg::with_name("greedy-perfect-match")
                 .long("greedy-perfect-match")
-                .help("This heuristic estimates the calculation of a perfect match of a bipartite graph between the goals and the crates, falling back to closest-box for initially unsatisfied nodes. It is admissible."))
+                .help("Same as --heuristic greedy-perfect-match"))
             .arg(Arg::with_name("closest-box")
                 .long("closest-box")
-                .help("This heuristic is the distance from the closest box to the goal. It is admissible."))
+                .help("Same as --heuristic closest-box"))
             .arg(Arg::with_name("goal-count")
                 .long("goal-count")
-                .help("This heuristic uses the number of goals which are not covered, which is faster but also less intelligent. It is admissible."))
-            .group(ArgGroup::with_name("heuristic")
-                .required(true)
+                .help("Same as --heuristic goal-count"))
+            .group(ArgGroup::with_name("heuristic-choice")
+                .arg("heuristic")
                 .arg("closest-box")
                 .arg("goal-count")
                 .arg("greedy-perfect-match"))
         )
+        .subcommand(
+            SubCommand::with_name("analyze")
+            .about("Answers questions about puzzles without solving them for optimal pushes.")
+            .arg(Arg::with_name("INPUT")
+                .required(true)
+                .index(1)
+                .help("Path to sokoban puzzle file to analyze"))
+            .arg(Arg::with_name("prove-unsolvable")
+                .long("prove-unsolvable")
+                .help("Searches every reachable state once, which either proves that a puzzle is unsolvable or finds a solution. \
+                       With --silent, prints \"status,states_explored,time_elapsed,pushes,moves,solution_string\" where status is \
+                       one of solvable, unsolvable, state_limit or time_limit."))
+            .arg(Arg::with_name("max-states")
+                .long("max-states")
+                .takes_value(true)
+                .value_name("N")
+                .help("Gives up after exploring N states [default: 1000000]"))
+            .arg(Arg::with_name("time-limit")
+                .long("time-limit")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("Gives up after this many seconds [default: 300]"))
+            .group(ArgGroup::with_name("analysis")
+                .required(true)
+                .arg("prove-unsolvable"))
+        )
+        .subcommand(
+            SubCommand::with_name("dedupe")
+            .about("Writes the levels of a .sok or .slc file without duplicates. Levels which only differ by rotation, reflection, \
+                    position or where the player stands in the same region are duplicates, & the first copy is kept.")
+            .arg(Arg::with_name("INPUT")
+                .required(true)
+                .index(1)
+                .help(".sok or .slc file to deduplicate"))
+            .arg(Arg::with_name("OUTPUT")
+                .required(true)
+                .index(2)
+                .help("file the unique levels are written to, as .slc if it has that extension & as .sok otherwise"))
+        )
+        .subcommand(
+            SubCommand::with_name("heuristics")
+            .about("Lists the heuristics which can be used with --heuristic, & whether they are admissible.")
+        )
+        .subcommand(
+            SubCommand::with_name("optimize")
+            .about("Shortens a solution by re-solving windows of its pushes with bounded IDA*, then re-walking between pushes. \
+                    With --silent, prints \"pushes_before,moves_before,pushes_after,moves_after,solution_string\".")
+            .arg(Arg::with_name("INPUT")
+                .required(true)
+                .index(1)
+                .help("Path to the sokoban puzzle file the solution is for"))
+            .arg(Arg::with_name("SOLUTION")
+                .required(true)
+                .index(2)
+                .help("The solution in LURD format, or a path to a file holding it"))
+            .arg(Arg::with_name("level")
+                .long("level")
+                .takes_value(true)
+                .value_name("N")
+                .default_value("1")
+                .help("Which puzzle of a .sok or .slc file the solution is for, counting from 1"))
+            .arg(Arg::with_name("window")
+                .long("window")
+                .takes_value(true)
+                .value_name("N")
+                .default_value("8")
+                .help("How many pushes are re-solved at a time. Larger windows find more but take longer."))
+            .arg(Arg::with_name("heuristic")
+                .long("heuristic")
+                .takes_value(true)
+                .value_name("NAME")
+                .default_value("hungarian")
+                .possible_values(&heuristic_names)
+                .help("The heuristic used by the window searches"))
+            .arg(Arg::with_name("time-limit")
+                .long("time-limit")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("How long all the window searches get together, after which the best solution so far is kept \
+                       [default: 300]"))
+        )
         .subcommand(
             SubCommand::with_name("puzzle-gen")
-            .about("Generates a .sok file filled with randomly generated puzzles, many of which may be unsolvable.")
+            .about("Generates a .sok file filled with randomly generated puzzles. Unless --mode reverse or --solvable-only is used, many of them may be unsolvable.")
             .arg(Arg::with_name("OUTPUT")
                 .required(true)
                 .index(1)
@@ -81,6 +211,100 @@ fn main() {
                 .required(true)
                 .index(6)
                 .help("How many walls to include in the puzzle"))
+            .arg(Arg::with_name("mode")
+                .long("mode")
+                .takes_value(true)
+                .possible_values(&["random", "reverse", "evolve"])
+                .default_value("random")
+                .help("random places crates & the player anywhere, so many puzzles are unsolvable. reverse starts with the crates \
+                       on the goals & pulls them away, so every puzzle is solvable, & writes the pulls played backwards as \
+                       each puzzle's solution. evolve mutates a population of reverse puzzles for --generations, keeping \
+                       the ones with the most pushes, & writes the best batch-size puzzles of every generation."))
+            .arg(Arg::with_name("shape")
+                .long("shape")
+                .takes_value(true)
+                .possible_values(&["rectangle", "rooms"])
+                .default_value("rectangle")
+                .help("rectangle fills a walled width by height room. rooms carves a few small rooms joined by corridors \
+                       out of the width by height area, which is redrawn for every puzzle."))
+            .arg(Arg::with_name("template")
+                .long("template")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with("shape")
+                .help("Generates puzzles inside the floor drawn in FILE, using # for walls, . for fixed goals & spaces, - \
+                       or _ for floor. Floor which isn't enclosed by walls is left out. Fixed goals count towards \
+                       goal-number, & width & height are ignored."))
+            .arg(Arg::with_name("pulls")
+                .long("pulls")
+                .takes_value(true)
+                .value_name("N")
+                .help("How many pulls --mode reverse makes [default: 50]"))
+            .arg(Arg::with_name("pushes")
+                .long("pushes")
+                .takes_value(true)
+                .value_name("MIN..MAX")
+                .help("Only keeps puzzles whose optimal solutions have this many pushes. Either end can be left out, ex: 10.. \
+                       Puzzles are solved when any target is given, & their measured difficulty is written to the .sok."))
+            .arg(Arg::with_name("moves")
+                .long("moves")
+                .takes_value(true)
+                .value_name("MIN..MAX")
+                .help("Only keeps puzzles whose optimal solutions have this many moves"))
+            .arg(Arg::with_name("nodes")
+                .long("nodes")
+                .takes_value(true)
+                .value_name("MIN..MAX")
+                .help("Only keeps puzzles which take the solver this many checked nodes"))
+            .arg(Arg::with_name("heuristic")
+                .long("heuristic")
+                .takes_value(true)
+                .value_name("NAME")
+                .possible_values(&heuristic_names)
+                .help("The heuristic used to solve puzzles for the difficulty targets & evolve mode [default: hungarian]"))
+            .arg(Arg::with_name("time-limit")
+                .long("time-limit")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("How long each puzzle is given to be solved for the difficulty targets & evolve mode [default: 10]"))
+            .arg(Arg::with_name("max-attempts")
+                .long("max-attempts")
+                .takes_value(true)
+                .value_name("N")
+                .help("How many puzzles in a row are made & thrown away before giving up, reporting why they failed. \
+                       Applies to each puzzle written, & each puzzle added to an evolve population. The puzzles written \
+                       so far are kept. [default: 200]"))
+            .arg(Arg::with_name("population")
+                .long("population")
+                .takes_value(true)
+                .value_name("N")
+                .help("How many puzzles are in each generation of --mode evolve [default: 20]"))
+            .arg(Arg::with_name("generations")
+                .long("generations")
+                .takes_value(true)
+                .value_name("N")
+                .help("How many generations --mode evolve runs for [default: 10]"))
+            .arg(Arg::with_name("seed")
+                .long("seed")
+                .takes_value(true)
+                .value_name("N")
+                .help("Seeds the random number generator, so the same puzzles can be made again. A random seed is used \
+                       otherwise. Either way, the seed is written into the .sok header."))
+            .arg(Arg::with_name("level-seed")
+                .long("level-seed")
+                .takes_value(true)
+                .value_name("N")
+                .conflicts_with("seed")
+                .help("Makes a single puzzle again, from the seed written under it. The other settings must be the same as \
+                       when it was made, & puzzles solved under a time limit may still come out differently. \
+                       Not used by --mode evolve."))
+            .arg(Arg::with_name("dedupe")
+                .long("dedupe")
+                .help("Skips puzzles which are the same as one already written, up to rotation, reflection, position \
+                       & where the player stands in its region"))
+            .arg(Arg::with_name("solvable-only")
+                .long("solvable-only")
+                .help("Searches each puzzle's states, retrying any which are unsolvable or too large to search"))
         )
         .get_matches();
 
@@ -90,30 +314,49 @@ fn main() {
         let filepath = matches.value_of("INPUT").unwrap();
 
         // Load file
-        let mut puzzles: Vec<TileMatrix>;
-        let mut is_dot_sok = false;
-        match level_reader::get_extension_from_filename(filepath) {
-            Some(s) => if s == "sok" {
-                puzzles = level_reader::read_sok(filepath, !is_silent);
-                is_dot_sok = true;
-            } else {  // any other file extension -- like .txt -- is allowed
-                puzzles = vec![level_reader::read_puzzle(filepath, !is_silent)];
-            },
-            None => {
-                puzzles = vec![level_reader::read_puzzle(filepath, !is_silent)];
-            },
-        } 
+        let mut collection: Collection = level_reader::read_levels(filepath, !is_silent);
+        let is_dot_sok = matches!(level_reader::get_extension_from_filename(filepath), Some("sok") | Some("slc"));
+        let mut puzzles: Vec<TileMatrix> = collection.levels.iter().map(|level| level.puzzle.clone()).collect();
 
         let mut deadlock_hashing: bool = false;
         if matches.is_present("deadlock-hashing") {
             deadlock_hashing = true;
         }
+        let goal_room_macros = matches.is_present("goal-room-macros");
 
-        if !is_dot_sok {
-            do_normal_solve(puzzles.pop().unwrap(), is_silent, deadlock_hashing, matches);
+        if !matches.is_present("bound-only") && !matches.is_present("heuristic-choice") {
+            println!("Command Error: a heuristic must be chosen, ex: --heuristic hungarian. Type \"rustsoko heuristics\" to list them.");
+            process::exit(1);
+        }
+        if let Some(out_path) = matches.value_of("write-solutions") {
+            if level_reader::get_extension_from_filename(out_path) == Some("slc") {
+                println!("Command Error: --write-solutions must write a .sok file, since .slc files have no place for solutions.");
+                process::exit(1);
+            }
+        }
+
+        if matches.is_present("bound-only") {
+            for (i, puzzle) in puzzles.into_iter().enumerate() {
+                do_bound_only(i+1, puzzle, is_silent, deadlock_hashing, goal_room_macros, &registry, matches);
+            }
         } else {
-            do_batch_solve(puzzles, is_silent, deadlock_hashing, matches);
+            let solutions = if !is_dot_sok {
+                vec![do_normal_solve(puzzles.pop().unwrap(), is_silent, deadlock_hashing, goal_room_macros, &registry, matches)]
+            } else {
+                do_batch_solve(puzzles, is_silent, deadlock_hashing, goal_room_macros, &registry, matches)
+            };
+            if let Some(out_path) = matches.value_of("write-solutions") {
+                write_solutions(&mut collection, solutions, out_path, is_silent, matches);
+            }
         }
+    } else if let Some(matches) = matches.subcommand_matches("analyze") {
+        do_prove_unsolvable(is_silent, &registry, matches);
+    } else if let Some(matches) = matches.subcommand_matches("optimize") {
+        do_optimize(is_silent, &registry, matches);
+    } else if let Some(matches) = matches.subcommand_matches("dedupe") {
+        do_dedupe(is_silent, matches);
+    } else if matches.subcommand_matches("heuristics").is_some() {
+        registry.print();
     } else if let Some(matches) = matches.subcommand_matches("puzzle-gen") { 
         let file_name = matches.value_of("OUTPUT").unwrap();
         if file_name.contains("/") {
@@ -126,40 +369,68 @@ fn main() {
         let goal_num = usize_parse( matches.value_of("goal-number").unwrap(), "goal-number" );
         let wall_num = usize_parse( matches.value_of("wall-number").unwrap(), "wall-number" );
 
-        if width <= 3 {
+        let shape = match matches.value_of("template") {
+            Some(template_path) => Shape::File(read_template(template_path)),
+            None if matches.value_of("shape") == Some("rooms") => Shape::Rooms,
+            None => Shape::Rectangle,
+        };
+
+        if batch_num == 0 {
+            println!("Command Error: batch-size must be non-zero");
+            process::exit(1);
+        } else if let Shape::File(template) = &shape {
+            if !template.fits(goal_num, wall_num) {
+                println!("Command Error: too many goals & walls for the template's floor.");
+                process::exit(1);
+            }
+        } else if width <= 3 {
             println!("Command Error: width too small -> width must be 4 or larger");
             process::exit(1);
         } else if height <= 3 {
             println!("Command Error: height too small -> width must be 4 or larger");
             process::exit(1);
-        } else if batch_num == 0 {
-            println!("Command Error: batch-size must be non-zero");
-            process::exit(1);
         } else if goal_num + wall_num > ((width * height) - ((2*width + 2*height) - 4)) / 2 {
             println!("Command Error: too many goal & wall spaces. goals + walls must be less than empty_spaces / 2.");
             process::exit(1);
         }
 
-        level_generator::make_sok(file_name, width, height, batch_num, goal_num, wall_num);
+        let mode = match matches.value_of("mode").unwrap() {
+            "reverse" => GenMode::Reverse,
+            "evolve" => GenMode::Evolve,
+            _ => GenMode::Random,
+        };
+        let settings = GenSettings {
+            width, height, shape, batch_num, goal_num, wall_num, mode,
+            pulls: usize_parse(matches.value_of("pulls").unwrap_or("50"), "pulls"),
+            solvable_only: matches.is_present("solvable-only"),
+            dedupe: matches.is_present("dedupe"),
+            targets: difficulty_targets(matches),
+            heuristic: matches.value_of("heuristic").unwrap_or("hungarian").to_string(),
+            time_limit: f32_parse(matches.value_of("time-limit").unwrap_or("10"), "time-limit"),
+            max_attempts: usize_parse(matches.value_of("max-attempts").unwrap_or("200"), "max-attempts"),
+            population: usize_parse(matches.value_of("population").unwrap_or("20"), "population"),
+            generations: usize_parse(matches.value_of("generations").unwrap_or("10"), "generations"),
+            seed: matches.value_of("seed").map(|s| u64_parse(s, "seed")),
+            level_seed: matches.value_of("level-seed").map(|s| u64_parse(s, "level-seed")),
+        };
+        if settings.mode == GenMode::Evolve && settings.population < 2 {
+            println!("Command Error: population must be at least 2");
+            process::exit(1);
+        }
+        level_generator::make_sok(file_name, &settings, &registry);
     }
 }
 
-fn do_normal_solve(puzzle: TileMatrix, is_silent: bool, deadlock_hashing: bool, matches: &ArgMatches) {
-    let mut solver: Option<IDAStarSolver> = None;
-
-    // clap assures that there will be exactly one heuristic.
-    if matches.is_present("closest-box") {
-        solver = Some(IDAStarSolver::new(puzzle, heuristic::closest_box, deadlock_hashing, !is_silent));
-    } else if matches.is_present("goal-count") {
-        solver = Some(IDAStarSolver::new(puzzle, heuristic::goal_count, deadlock_hashing, !is_silent));
-    } else if matches.is_present("greedy-perfect-match") {
-        solver = Some(IDAStarSolver::new(puzzle, heuristic::greedy_perfect_match, deadlock_hashing, !is_silent));
-    }
+fn do_normal_solve(puzzle: TileMatrix, is_silent: bool, deadlock_hashing: bool, goal_room_macros: bool, 
+                   registry: &HeuristicRegistry, matches: &ArgMatches) -> Option<Vec<Action>> {
+    let mut solver: Option<IDAStarSolver> = make_solver(puzzle, deadlock_hashing, goal_room_macros, is_silent, registry, matches);
+    collect_all_optimal(&mut solver, matches);
+    let solution: Option<Vec<Action>>;
 
     if matches.is_present("profile") {
         // Profile execution
         let guard = pprof::ProfilerGuard::new(100).unwrap();
-        execute_solver(solver, is_silent);
+        solution = execute_solver(solver, is_silent);
         if let Ok(report) = guard.report().build() {
             let file = File::create("flamegraph.svg").unwrap();
             report.flamegraph(file).unwrap();
@@ -167,34 +438,29 @@ fn do_normal_solve(puzzle: TileMatrix, is_silent: bool, deadlock_hashing: bool,
         };
 
     } else {
-        execute_solver(solver, is_silent);
+        solution = execute_solver(solver, is_silent);
     }
+    solution
 }
 
-fn do_batch_solve(mut puzzles: Vec<TileMatrix>, is_silent: bool, deadlock_hashing: bool, matches: &ArgMatches) {
-    let mut i = 0;
-    for puzzle in puzzles.drain(..) {
-        let mut solver: Option<IDAStarSolver> = None;
+fn do_batch_solve(mut puzzles: Vec<TileMatrix>, is_silent: bool, deadlock_hashing: bool, goal_room_macros: bool, 
+                  registry: &HeuristicRegistry, matches: &ArgMatches) -> Vec<Option<Vec<Action>>> {
+    let mut solutions: Vec<Option<Vec<Action>>> = Vec::new();
+    for (i, puzzle) in puzzles.drain(..).enumerate() {
         if !is_silent {
             println!("======================================================");
             println!("Starting puzzle {}:", i+1);
             puzzle.print();
         }
 
-        // clap assures that there will be exactly one heuristic.
-        if matches.is_present("closest-box") {
-            solver = Some(IDAStarSolver::new(puzzle, heuristic::closest_box, deadlock_hashing, !is_silent));
-        } else if matches.is_present("goal-count") {
-            solver = Some(IDAStarSolver::new(puzzle, heuristic::goal_count, deadlock_hashing, !is_silent));
-        } else if matches.is_present("greedy-perfect-match") {
-            solver = Some(IDAStarSolver::new(puzzle, heuristic::greedy_perfect_match, deadlock_hashing, !is_silent));
-        }
+        let mut solver: Option<IDAStarSolver> = make_solver(puzzle, deadlock_hashing, goal_room_macros, is_silent, registry, matches);
+        collect_all_optimal(&mut solver, matches);
 
         if matches.is_present("profile") {
             // Profile execution
             // when profiling, will create N flame graphs.
             let guard = pprof::ProfilerGuard::new(100).unwrap();
-            execute_solver(solver, is_silent);
+            solutions.push(execute_solver(solver, is_silent));
             if let Ok(report) = guard.report().build() {
                 let file = File::create( format!("flamegraph{}.svg", i+1) ).unwrap();
                 report.flamegraph(file).unwrap();
@@ -202,26 +468,364 @@ fn do_batch_solve(mut puzzles: Vec<TileMatrix>, is_silent: bool, deadlock_hashin
             };
 
         } else {
-            execute_solver(solver, is_silent);
+            solutions.push(execute_solver(solver, is_silent));
+        }
+    }
+    solutions
+}
+
+fn do_optimize(is_silent: bool, registry: &HeuristicRegistry, matches: &ArgMatches) {
+    let filepath = matches.value_of("INPUT").unwrap();
+    let n = usize_parse(matches.value_of("level").unwrap(), "level");
+    // levels count from 1, so 0 is out of range like any level past the end.
+    let levels = level_reader::read_levels(filepath, false).levels;
+    let puzzle = match n.checked_sub(1).and_then(|i| levels.into_iter().nth(i)) {
+        Some(level) => level.puzzle,
+        None => {
+            println!("Command Error: invalid level -> {} does not have puzzle {}", filepath, n);
+            process::exit(1);
+        }
+    };
+    if !is_silent {
+        puzzle.print();
+    }
+
+    // the solution can be given directly, or as a file.
+    let solution = matches.value_of("SOLUTION").unwrap();
+    let solution = std::fs::read_to_string(solution).unwrap_or_else(|_| solution.to_string());
+    let actions = match Action::from_string(&solution) {
+        Some(actions) => actions,
+        None => {
+            println!("Command Error: invalid solution -> must only contain the characters lurdLURD");
+            process::exit(1);
+        }
+    };
+
+    let window = usize_parse(matches.value_of("window").unwrap(), "window");
+    let mut optimizer = Optimizer::new(puzzle.clone(), registry, matches.value_of("heuristic").unwrap(), window, !is_silent);
+    if let Some(s) = matches.value_of("time-limit") {
+        optimizer.set_time_limit(f32_parse(s, "time-limit"));
+    }
+    let (before, improved) = match util::verify_solution(&puzzle, &actions).and_then(|b| Ok((b, optimizer.optimize(&actions)?))) {
+        Ok(result) => result,
+        Err(e) => {
+            println!("Error: not a valid solution -> {}", e);
+            process::exit(1);
+        }
+    };
+    let after = util::verify_solution(&puzzle, &improved).unwrap();
+
+    if is_silent {
+        println!("{},{},{},{},{}", before.0, before.1, after.0, after.1, Action::to_string(&improved));
+    } else {
+        println!("Before: pushes {}, moves {}", before.0, before.1);
+        println!("After: pushes {}, moves {} (saved {} pushes, {} moves)", after.0, after.1, before.0 - after.0, 
+                 before.1 as isize - after.1 as isize);
+        println!("Optimized solution is: {}", Action::to_string(&improved));
+    }
+}
+
+fn do_dedupe(is_silent: bool, matches: &ArgMatches) {
+    let filepath = matches.value_of("INPUT").unwrap();
+    let mut collection = level_reader::read_levels(filepath, !is_silent);
+    let level_count = collection.levels.len();
+
+    // the first copy of each level is kept, along with its metadata.
+    let mut first_copies: HashMap<u64, String> = HashMap::new();
+    collection.levels.retain(|level| {
+        match first_copies.get(&level.puzzle.canonical_hash()) {
+            Some(original) => {
+                if !is_silent {
+                    println!("Level {} is a duplicate of level {}", level.title, original);
+                }
+                false
+            },
+            None => {
+                first_copies.insert(level.puzzle.canonical_hash(), level.title.clone());
+                true
+            },
+        }
+    });
+
+    level_reader::write_collection(matches.value_of("OUTPUT").unwrap(), &collection);
+    let unique = collection.levels.len();
+    if is_silent {
+        println!("{},{},{}", level_count, unique, level_count - unique);
+    } else {
+        println!("Kept {} of {} levels", unique, level_count);
+    }
+}
+
+fn do_prove_unsolvable(is_silent: bool, registry: &HeuristicRegistry, matches: &ArgMatches) {
+    let filepath = matches.value_of("INPUT").unwrap();
+    let puzzles: Vec<TileMatrix> = level_reader::read_levels(filepath, false).levels.into_iter().map(|level| level.puzzle).collect();
+    let max_states = usize_parse(matches.value_of("max-states").unwrap_or("1000000"), "max-states");
+
+    for (i, puzzle) in puzzles.into_iter().enumerate() {
+        if !is_silent {
+            println!("======================================================");
+            println!("Puzzle {}:", i+1);
+            puzzle.print();
+        }
+
+        // the heuristic only orders successors here, so the cheapest one is used.
+        let mut solver = IDAStarSolver::new(puzzle, registry.make("goal-count").unwrap(), false, false, false);
+        if let Some(s) = matches.value_of("time-limit") {
+            solver.set_time_limit(f32_parse(s, "time-limit"));
+        }
+        let (end, states, solution) = solver.exhaust(max_states);
+        let status = if end == SearchEnd::Solved { "solvable" } else { end.to_str() };
+        let pushes = solution.iter().filter(|a| a.is_push()).count();
+
+        if is_silent {
+            println!("{},{},{},{},{},{}", status, states, solver.time_elapsed(), pushes, solution.len(), Action::to_string(&solution));
+        } else {
+            match end {
+                SearchEnd::Solved => println!("Solvable, found after exploring {} states: {}", states, Action::to_string(&solution)),
+                SearchEnd::Unsolvable => println!("Unsolvable, proven by exploring {} states", states),
+                _ => println!("Unknown, gave up after exploring {} states ({})", states, status),
+            }
+        }
+    }
+}
+
+// Prints the starting estimate of each heuristic, then runs IDA* with the chosen heuristic until the time limit to find
+// the highest proven lower bound.
+fn do_bound_only(level: usize, puzzle: TileMatrix, is_silent: bool, deadlock_hashing: bool, goal_room_macros: bool, 
+                 registry: &HeuristicRegistry, matches: &ArgMatches) {
+    // building heuristics (like pattern databases) counts against the time limit, as well as the search.
+    let timer = Instant::now();
+    let time_limit = match matches.value_of("time-limit") {
+        Some(s) => f32_parse(s, "time-limit"),
+        None => TIME_LIMIT as f32,
+    };
+    let time_left = || (time_limit - timer.elapsed().as_secs_f32()).max(0.0);
+
+    // the searched heuristic is built first so it always gets to run, & its solver is reused for the search.
+    let name = heuristic_name(matches).unwrap_or("hungarian");
+    let mut heuristic = registry.make(name).unwrap();
+    heuristic.set_init_time(time_left());
+    let mut solver = IDAStarSolver::new(puzzle.clone(), heuristic, deadlock_hashing, goal_room_macros, false);
+
+    // estimates are None for heuristics which were skipped because the time ran out.
+    let estimates: Vec<(&str, Option<usize>, bool)> = registry.names().into_iter().map(|other| {
+        let mut heuristic = registry.make(other).unwrap();
+        let admissible = heuristic.is_admissible();
+        if other == name {
+            return (other, Some(solver.initial_h()), admissible);
+        } else if time_left() <= 0.0 {
+            return (other, None, admissible);
         }
+        heuristic.set_init_time(time_left());
+        let estimator = IDAStarSolver::new(puzzle.clone(), heuristic, false, false, false);
+        (other, Some(estimator.initial_h()), admissible)
+    }).collect();
+
+    solver.set_time_limit(time_left());
+    let (bound, end) = solver.lower_bound();
+    let time_elapsed = timer.elapsed().as_secs_f32();
 
-        i += 1;
+    if is_silent {
+        println!("bound,{},{},{},{},{},{},{}", level, name, end.to_str(), bound, solver.is_push_optimal(), time_elapsed, solver.nodes_checked());
+        for (name, h, admissible) in estimates {
+            match h {
+                Some(usize::MAX) => println!("h,{},{},unsolvable,{}", level, name, admissible),
+                Some(h) => println!("h,{},{},{},{}", level, name, h, admissible),
+                None => println!("h,{},{},skipped,{}", level, name, admissible),
+            }
+        }
+    } else {
+        for (name, h, admissible) in estimates {
+            match h {
+                Some(usize::MAX) => println!("{}: unsolvable", name),
+                Some(h) => println!("{}: {}{}", name, h, if admissible { "" } else { " (not admissible)" }),
+                None => println!("{}: skipped, out of time", name),
+            }
+        }
+        match end {
+            SearchEnd::Solved => println!("Solved with {} pushes", bound),
+            SearchEnd::Unsolvable => println!("No solution exists"),
+            _ => println!("Lower bound after {}s: {} pushes", time_elapsed, bound),
+        }
+        if !solver.is_push_optimal() {
+            println!("The bound is not proven, since the search may cut off optimal solutions.");
+        }
+        println!("nodes checked = {}", solver.nodes_checked());
+    }
+}
+
+// Creates a solver with the chosen heuristic & time limit, or None if no heuristic was chosen.
+fn make_solver(puzzle: TileMatrix, deadlock_hashing: bool, goal_room_macros: bool, is_silent: bool, 
+               registry: &HeuristicRegistry, matches: &ArgMatches) -> Option<IDAStarSolver> {
+    let mut solver = IDAStarSolver::new(puzzle, make_heuristic(registry, matches)?, deadlock_hashing, goal_room_macros, !is_silent);
+    if let Some(s) = matches.value_of("time-limit") {
+        solver.set_time_limit(f32_parse(s, "time-limit"));
+    }
+    Some(solver)
+}
+
+// clap assures that there will be at most one heuristic, & main that there is one unless using --bound-only.
+fn heuristic_name<'a>(matches: &'a ArgMatches) -> Option<&'a str> {
+    match matches.value_of("heuristic") {
+        Some(name) => Some(name),
+        None => ["closest-box", "goal-count", "greedy-perfect-match"].iter().copied().find(|flag| matches.is_present(flag)),
+    }
+}
+
+fn make_heuristic(registry: &HeuristicRegistry, matches: &ArgMatches) -> Option<Box<dyn Heuristic>> {
+    registry.make(heuristic_name(matches)?)
+}
+
+// with --all-optimal, has the solver collect up to max-solutions distinct solutions.
+fn collect_all_optimal(solver: &mut Option<IDAStarSolver>, matches: &ArgMatches) {
+    if let Some(s) = solver.as_mut() {
+        if matches.is_present("all-optimal") {
+            s.collect_all_optimal(usize_parse(matches.value_of("max-solutions").unwrap_or("100"), "max-solutions"));
+        }
+    }
+}
+
+fn print_all_optimal(d: &DistinctSolutions, is_silent: bool) {
+    let pushes = |actions: &Vec<Action>| Action::to_string(actions).chars().filter(|c| c.is_uppercase()).count();
+
+    if is_silent {
+        println!("all_optimal,{},{}", d.solutions.len(), d.capped);
+        for actions in &d.solutions {
+            println!("{},{},{}", pushes(actions), actions.len(), Action::to_string(actions));
+        }
+    } else {
+        if d.capped {
+            println!("Distinct solutions: more than {} (only the first {} are listed)", d.solutions.len(), d.solutions.len());
+        } else {
+            println!("Distinct solutions: {}", d.solutions.len());
+        }
+        for (i, actions) in d.solutions.iter().enumerate() {
+            println!("{}: pushes {}, moves {}: {}", i + 1, pushes(actions), actions.len(), Action::to_string(actions));
+        }
     }
 }
 
-fn execute_solver(solver: Option<IDAStarSolver>, is_silent: bool) {
+// runs the solver & prints its solution, returning the solution's actions.
+fn execute_solver(solver: Option<IDAStarSolver>, is_silent: bool) -> Option<Vec<Action>> {
     if let Some(mut s) = solver {
         let solution = s.solve();
-        if !is_silent {
+        if !is_silent && s.is_push_optimal() && s.is_move_optimal() {
             print!("Optimal solution is: ");
+        } else if !is_silent && s.is_push_optimal() {
+            print!("Push optimal solution (time ran out, so it may not have the fewest moves) is: ");
+        } else if !is_silent {
+            print!("Solution (may not be push optimal) is: ");
         }
         println!("{}", solution);
+        if let Some(distinct) = s.distinct_solutions() {
+            print_all_optimal(distinct, is_silent);
+        }
+        s.solution()
     } else {
-        println!("Command Error: A heuristic must be stated. ex: --closest-box");
+        println!("Command Error: A heuristic must be stated. ex: --heuristic hungarian");
         process::exit(1);
     }
 }
 
+// Adds each solution to its level & writes the collection to out_path.
+fn write_solutions(collection: &mut Collection, solutions: Vec<Option<Vec<Action>>>, out_path: &str, is_silent: bool,
+                   matches: &ArgMatches) {
+    let found_by = solver_settings(matches);
+    let mut added = 0;
+    for (level, solution) in collection.levels.iter_mut().zip(solutions) {
+        if let Some(actions) = solution {
+            if level.add_solution(&actions, &found_by) {
+                added += 1;
+            }
+        }
+    }
+
+    level_reader::write_collection(out_path, collection);
+    if !is_silent {
+        println!("Wrote {} with {} new or improved solutions", out_path, added);
+    }
+}
+
+// how a solution was found, written into its .sok label.
+fn solver_settings(matches: &ArgMatches) -> String {
+    let mut settings = format!("rustsoko --heuristic {}", heuristic_name(matches).unwrap_or("none"));
+    for flag in ["deadlock-hashing", "goal-room-macros"] {
+        if matches.is_present(flag) {
+            settings.push_str(&format!(" --{}", flag));
+        }
+    }
+    if let Some(s) = matches.value_of("time-limit") {
+        settings.push_str(&format!(" --time-limit {}", s));
+    }
+    settings
+}
+
+// Loads a puzzle-gen template, exiting if it can't be read.
+fn read_template(template_path: &str) -> Template {
+    let template_string = match std::fs::read_to_string(template_path) {
+        Ok(s) => s,
+        Err(_) => {
+            println!("Error: Input path does not exist, or cannot be opened.");
+            process::exit(1);
+        },
+    };
+    match Template::from_string(&template_string) {
+        Ok(template) => template,
+        Err(reason) => {
+            println!("Error: template is malformed.\nreason: {}", reason);
+            process::exit(1);
+        },
+    }
+}
+
+// The difficulty targets given to puzzle-gen, or None if there aren't any.
+fn difficulty_targets(matches: &ArgMatches) -> Option<DifficultyTargets> {
+    if !["pushes", "moves", "nodes"].iter().any(|target| matches.is_present(target)) {
+        return None;
+    }
+    let range = |name: &str| matches.value_of(name).map(|s| range_parse(s, name)).unwrap_or((0, usize::MAX));
+    Some(DifficultyTargets {
+        pushes: range("pushes"),
+        moves: range("moves"),
+        nodes: range("nodes"),
+    })
+}
+
+// reads an inclusive range written as MIN..MAX, MIN.., ..MAX or a single number.
+fn range_parse(s: &str, error_kind: &str) -> (usize, usize) {
+    match s.find("..") {
+        Some(i) => {
+            let min = if i == 0 { 0 } else { usize_parse(&s[..i], error_kind) };
+            let max = if i + 2 == s.len() { usize::MAX } else { usize_parse(&s[i+2..], error_kind) };
+            (min, max)
+        },
+        None => {
+            let n = usize_parse(s, error_kind);
+            (n, n)
+        },
+    }
+}
+
+fn f32_parse(s: &str, error_kind: &str) -> f32 {
+    match s.parse::<f32>() {
+        Ok(num) if num >= 0.0 => num,
+        _ => {
+            println!("Command Error: invalid {} -> must be a positive number", error_kind);
+            process::exit(1);
+        }
+    }
+}
+
+fn u64_parse(s: &str, error_kind: &str) -> u64 {
+    match s.parse::<u64>() {
+        Ok(num) => num,
+        Err(_) => {
+            println!("Command Error: invalid {} -> must be integer", error_kind);
+            process::exit(1);
+        }
+    }
+}
+
 fn usize_parse(s: &str, error_kind: &str) -> usize {
     match s.parse::<usize>() {
         Ok(num) => num,
@@ -0,0 +1,168 @@
+use crate::types::{Tile, Point2D, TileMatrix, Action, CrateSet};
+use std::time::Instant;
+
+use crate::ida_star_solver::{IDAStarSolver, TIME_LIMIT};
+use crate::heuristic::HeuristicRegistry;
+use crate::util;
+
+// Improves an existing solution by cutting its pushes into windows, then searching for a shorter way between the
+// states at either end of each window. Window searches are bounded by the pushes they replace, so they stay small
+// even when the whole level is too hard to solve optimally. The walking is redone after every change, & a change is
+// only kept if the full solution still verifies with fewer (pushes, moves). The time limit covers the whole pass, & 
+// each window search gets whatever is left of it.
+pub struct Optimizer<'a> {
+    puzzle: TileMatrix,
+    walls: TileMatrix,  // walls & floor only, which the window levels are built from.
+    registry: &'a HeuristicRegistry,
+    heuristic: String,
+    window: usize,
+    walks: util::PathCache,
+    time_limit: f32,
+    timer: Instant,
+    debug: bool,
+}
+impl<'a> Optimizer<'a> {
+    pub fn new(puzzle: TileMatrix, registry: &'a HeuristicRegistry, heuristic: &str, window: usize,
+               debug: bool) -> Optimizer<'a> {
+        let mut walls = puzzle.clone();
+        for tile in walls.data.iter_mut() {
+            if *tile != Tile::Wall {
+                *tile = Tile::Floor;
+            }
+        }
+        let walks = util::PathCache::new(puzzle.data.len());
+        Optimizer { puzzle, walls, registry, heuristic: heuristic.to_string(), window: window.max(2), walks, 
+                    time_limit: TIME_LIMIT as f32, timer: Instant::now(), debug }
+    }
+
+    pub fn set_time_limit(&mut self, seconds: f32) {
+        self.time_limit = seconds;
+    }
+
+    fn time_left(&self) -> f32 {
+        self.time_limit - self.timer.elapsed().as_secs_f32()
+    }
+
+    // Returns the improved solution, which is never worse than the original. Errors if the original isn't a solution.
+    // When the time runs out, the best solution so far is returned.
+    pub fn optimize(&mut self, actions: &[Action]) -> Result<Vec<Action>, String> {
+        self.timer = Instant::now();
+        let mut best_score = util::verify_solution(&self.puzzle, actions)?;
+        let mut best = actions.to_vec();
+
+        // keep passing over the solution until no window can be improved.
+        let mut improved = true;
+        while improved {
+            improved = false;
+            let mut start = 0;
+            while start < best_score.0 && self.time_left() > 0.0 {
+                let end = (start + self.window).min(best_score.0);
+                if let Some(candidate) = self.improve_window(&best, start, end)? {
+                    let score = util::verify_solution(&self.puzzle, &candidate)?;
+                    if score < best_score {
+                        if self.debug {
+                            println!("DEBUG: pushes {}..{} improved to {} pushes, {} moves", start, end, score.0, score.1);
+                        }
+                        best = candidate;
+                        best_score = score;
+                        improved = true;
+                        continue;  // the window may be improved again now that its end has moved.
+                    }
+                }
+                start += (self.window / 2).max(1);
+            }
+        }
+        Ok(best)
+    }
+
+    // Searches for a path from the state after $start pushes to the state after $end pushes, using at most as many
+    // pushes. Returns the whole solution with the new pushes spliced in & re-walked.
+    fn improve_window(&mut self, actions: &[Action], start: usize, end: usize) -> Result<Option<Vec<Action>>, String> {
+        let states = util::replay_pushes(&self.puzzle, actions)?;
+        let (start_crates, start_player) = &states[start];
+        let (end_crates, end_player) = &states[end];
+
+        let level = self.window_level(start_crates, *start_player, end_crates);
+        let heuristic = self.registry.make(&self.heuristic).ok_or(format!("unknown heuristic {}", self.heuristic))?;
+        let mut solver = IDAStarSolver::new(level.clone(), heuristic, false, false, false);
+        solver.set_target_player(*end_player);
+        solver.set_max_pushes(end - start);
+        solver.set_time_limit(self.time_left().max(0.0));
+        let window_actions = match solver.solve_actions() {
+            Some(window_actions) => window_actions,
+            None => return Ok(None),
+        };
+
+        let mut pushes = push_steps(&self.puzzle, actions);
+        let window_pushes = push_steps(&level, &window_actions);
+        pushes.splice(start..end, window_pushes);
+        let (crates, player) = states[0].clone();
+        Ok(Some(util::walk_pushes(&mut self.walks, &self.walls, crates, player, &pushes)))
+    }
+
+    // The level between two states of the solution, where the goals are wherever the crates need to end up.
+    fn window_level(&self, crates: &CrateSet, player: Point2D, goals: &CrateSet) -> TileMatrix {
+        let mut level = self.walls.clone();
+        for goal in goals.iter() {
+            level.set(goal, Tile::Goal);
+        }
+        for crate_pos in crates.iter() {
+            let tile = if goals.get(crate_pos) { Tile::CrateGoal } else { Tile::Crate };
+            level.set(crate_pos, tile);
+        }
+        let tile = if goals.get(player) { Tile::PlayerGoal } else { Tile::Player };
+        level.set(player, tile);
+        level
+    }
+
+}
+
+// The (crate position, push) steps of a valid list of actions.
+fn push_steps(puzzle: &TileMatrix, actions: &[Action]) -> Vec<(Point2D, Action)> {
+    let states = util::replay_pushes(puzzle, &[]).unwrap();
+    let mut player = states[0].1;
+
+    let mut pushes: Vec<(Point2D, Action)> = Vec::new();
+    for action in actions {
+        player = player.from(*action);
+        if action.is_push() {
+            pushes.push((player, *action));
+        }
+    }
+    pushes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEVEL: &str = "\
+#######
+#     #
+# @$ .#
+#     #
+#######
+";
+
+    fn optimize(lurd: &str) -> Vec<Action> {
+        let registry = HeuristicRegistry::new();
+        let mut optimizer = Optimizer::new(TileMatrix::from_string(LEVEL), &registry, "hungarian", 8, false);
+        optimizer.optimize(&Action::from_string(lurd).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn long_solution_gets_shorter() {
+        // pushes the crate past the goal's side & back before finishing.
+        let long = "RurrdLulldRR";
+        let level = TileMatrix::from_string(LEVEL);
+        assert_eq!(util::verify_solution(&level, &Action::from_string(long).unwrap()), Ok((4, 12)));
+        let optimized = optimize(long);
+        assert_eq!(util::verify_solution(&level, &optimized), Ok((2, 2)));
+        assert_eq!(Action::to_string(&optimized), "RR");
+    }
+
+    #[test]
+    fn optimal_solution_is_unchanged() {
+        assert_eq!(Action::to_string(&optimize("RR")), "RR");
+    }
+}
@@ -1,4 +1,5 @@
 use bit_vec::BitVec;
+use std::fmt;
 use std::process;
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
@@ -14,18 +15,23 @@ pub enum Tile {
 impl Tile {
     // for freeze deadlocks
     pub fn is_freezable(&self) -> bool {
-        match self {
-            Tile::Wall => true,
-            Tile::Crate => true,
-            Tile::CrateGoal => true,  // this is true because self is crate.
-            _ => false,
-        }
+        // crate goals are freezable because self is crate.
+        matches!(self, Tile::Wall | Tile::Crate | Tile::CrateGoal)
     }
 
     pub fn is_pure_crate(&self) -> bool {
+        matches!(self, Tile::Crate)
+    }
+
+    pub fn to_char(self) -> char {
         match self {
-            Tile::Crate => true,
-            _ => false,
+            Tile::Wall => '#',
+            Tile::Player => '@',
+            Tile::PlayerGoal => '+',
+            Tile::Crate => '$',
+            Tile::CrateGoal => '*',
+            Tile::Goal => '.',
+            Tile::Floor => ' ',
         }
     }
 }
@@ -36,36 +42,28 @@ pub struct TileMatrix {
     pub data: Vec<Tile>,
 }
 impl TileMatrix {
-    pub fn from_string(puzzle_string: &str) -> TileMatrix {
+    // Parses a puzzle, or returns the reason it is malformed.
+    pub fn try_from_string(puzzle_string: &str) -> Result<TileMatrix, String> {
         match puzzle_string.find('\n') {
             Some(v) => v,
-            None => {
-                println!("Error: puzzle file is malformed.\nreason: must include newline.");
-                process::exit(1);
-            },
+            None => return Err("must include newline.".to_string()),
         };
     
         // find puzzle width.
         let mut puzzle_width: usize = 0;
         let mut beg_pos: usize = 0;
-        loop {
-            match puzzle_string[beg_pos..].find('\n') {
-                Some(v) => {
-                    beg_pos += v + 1;
-                    if v > puzzle_width {
-                        puzzle_width = v;
-                    }
-                },
-                None => break,
-            };
+        while let Some(v) = puzzle_string[beg_pos..].find('\n') {
+            beg_pos += v + 1;
+            if v > puzzle_width {
+                puzzle_width = v;
+            }
             if beg_pos > puzzle_string.len() { 
                 break; 
             }
         }
     
         if puzzle_width == 0 {
-            println!("Error: puzzle file is malformed.\nreason: newline cannot be first character.");
-            process::exit(1);
+            return Err("newline cannot be first character.".to_string());
         }
     
         let mut player_count = 0;
@@ -113,28 +111,41 @@ impl TileMatrix {
                 },
                 '\r' => index -= 1,
                 _ => {
-                    println!("Error: puzzle file is malformed.\nreason: invalid character in puzzle file, \"{}\".\npuzzle can only contain the characters \"#@+$*. \"", ch);
-                    process::exit(1);
+                    return Err(format!("invalid character in puzzle file, \"{}\".\npuzzle can only contain the characters \"#@+$*. \"", ch));
                 },
             };
             index += 1;
         }
     
         if player_count != 1 {
-            println!("Error: puzzle file is malformed.\nreason: There must be exactly 1 player tile, \"@\".");
-            process::exit(1);
+            return Err("There must be exactly 1 player tile, \"@\".".to_string());
         }
     
         if crate_count != goal_count {
-            println!("Error: puzzle file is malformed.\nreason: There must be the same number of goals and crates.");
-            process::exit(1);
+            return Err("There must be the same number of goals and crates.".to_string());
         }
-    
-        TileMatrix {
+
+        let puzzle = TileMatrix {
             width: puzzle_width, 
             data: tile_vec,
+        };
+        if !puzzle.is_enclosed() {
+            return Err("The player, crates and goals must be surrounded by walls.".to_string());
+        }
+        Ok(puzzle)
+    }
+
+    // Like try_from_string, but exits with the reason when the puzzle is malformed.
+    pub fn from_string(puzzle_string: &str) -> TileMatrix {
+        match TileMatrix::try_from_string(puzzle_string) {
+            Ok(puzzle) => puzzle,
+            Err(reason) => {
+                println!("Error: puzzle file is malformed.\nreason: {}", reason);
+                process::exit(1);
+            },
         }
     }
+
     pub fn from_string_bare(puzzle_string: &str) -> TileMatrix {
         match puzzle_string.find('\n') {
             Some(v) => v,
@@ -147,16 +158,11 @@ impl TileMatrix {
         // find puzzle width.
         let mut puzzle_width: usize = 0;
         let mut beg_pos: usize = 0;
-        loop {
-            match puzzle_string[beg_pos..].find('\n') {
-                Some(v) => {
-                    beg_pos += v + 1;
-                    if v > puzzle_width {
-                        puzzle_width = v;
-                    }
-                },
-                None => break,
-            };
+        while let Some(v) = puzzle_string[beg_pos..].find('\n') {
+            beg_pos += v + 1;
+            if v > puzzle_width {
+                puzzle_width = v;
+            }
             if beg_pos > puzzle_string.len() { 
                 break; 
             }
@@ -209,9 +215,34 @@ impl TileMatrix {
     pub fn set(&mut self, p: Point2D, val: Tile) {
         self.data[p.y * self.width + p.x] = val;
     }
-    pub fn apply_action_and_move(&mut self, action: Action, crate_start: Point2D, old_map: &TileMatrix, inital_player: Point2D) {
+
+    // Whether walls cut the player, crates & goals off from the edge of the map. The searches step to neighbouring
+    // squares without checking bounds, so every level they're given must be enclosed.
+    pub fn is_enclosed(&self) -> bool {
+        let width = self.width;
+        let mut seen = vec![false; self.data.len()];
+        let mut stack: Vec<usize> = (0..self.data.len())
+            .filter(|i| !matches!(self.data[*i], Tile::Wall | Tile::Floor))
+            .collect();
+        while let Some(current) = stack.pop() {
+            if seen[current] {
+                continue;
+            }
+            seen[current] = true;
+            if current % width == 0 || current % width == width - 1 || current < width || current + width >= self.data.len() {
+                return false;
+            }
+            for next in [current - 1, current + 1, current - width, current + width] {
+                if !seen[next] && self.data[next] != Tile::Wall {
+                    stack.push(next);
+                }
+            }
+        }
+        true
+    }
+    pub fn apply_action_and_move(&mut self, action: Action, crate_start: Point2D, inital_player: Point2D) {
         let crate_end = crate_start.from(action);
-        match old_map.get(inital_player) { // update the position the player leaves from.
+        match self.get(inital_player) { // update the position the player leaves from.
             Tile::Player => self.set(inital_player, Tile::Floor),
             Tile::PlayerGoal => self.set(inital_player, Tile::Goal),
             _ => (),
@@ -227,25 +258,6 @@ impl TileMatrix {
             _ => self.set(crate_end, Tile::Crate),
         }
     }
-    pub fn undo_action(&mut self, action: Action, player_start: Point2D) {
-        let empty_pos = player_start.from(action.inverse());
-        let crate_start = player_start.from(action);
-        match self.get(empty_pos) { // update the position the player leaves from.
-            Tile::Floor => self.set(empty_pos, Tile::Player),
-            Tile::Goal => self.set(empty_pos, Tile::PlayerGoal),
-            _ => (),
-        }
-        match self.get(player_start) {  // update the position where the player ends up
-            Tile::Player  => self.set(player_start, Tile::Crate),
-            Tile::PlayerGoal  => self.set(player_start, Tile::CrateGoal),
-            _ => (),
-        }
-        match self.get(crate_start) {  // update position where the crate ends up
-            Tile::Crate => self.set(crate_start, Tile::Floor),
-            Tile::CrateGoal => self.set(crate_start, Tile::Goal),
-            _ => (),
-        }
-    }
     pub fn print(&self) {
         print!("  ");
         for i in 0..self.width {
@@ -253,7 +265,7 @@ impl TileMatrix {
                 print!("{}", i);
             }
         }
-        print!("\n");
+        println!();
         for (i, tile) in self.data.iter().enumerate() {
             if i % self.width == 0 {
                 if i / self.width >= 10 {
@@ -262,19 +274,98 @@ impl TileMatrix {
                     print!("\n{} ", i / self.width);
                 }
             }
-            print!("{}", match tile {
-                Tile::Wall => '#',
-                Tile::Player => '@',
-                Tile::PlayerGoal => '+',
-                Tile::Crate => '$',
-                Tile::CrateGoal => '*',
-                Tile::Goal => '.',
-                Tile::Floor => ' ',
-            });
-            
+            print!("{}", tile.to_char());
         }
         print!("\n\n");
     }
+
+    // The same puzzle in a standard form, so that copies which are rotated, reflected, moved around or have the player
+    // standing elsewhere in the same region all come out equal. The puzzle is trimmed, then of its 8 rotations &
+    // reflections, the one whose text sorts first is kept, with the player on the first square of its region.
+    pub fn canonical(&self) -> TileMatrix {
+        let trimmed = self.trimmed();
+        (0..8).map(|symmetry| trimmed.transformed(symmetry).with_normal_player())
+            .min_by_key(|puzzle| puzzle.to_string())
+            .unwrap()
+    }
+
+    // 64 bit FNV-1a hash of the canonical form's text, which stays the same between runs & builds.
+    pub fn canonical_hash(&self) -> u64 {
+        self.canonical().to_string().bytes()
+            .fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+    }
+
+    // removes rows & columns of floor from the edges.
+    fn trimmed(&self) -> TileMatrix {
+        let height = self.data.len() / self.width;
+        let used: Vec<Point2D> = (0..self.data.len())
+            .filter(|i| self.data[*i] != Tile::Floor)
+            .map(|i| Point2D::new(i % self.width, i / self.width))
+            .collect();
+        let left = used.iter().map(|p| p.x).min().unwrap_or(0);
+        let right = used.iter().map(|p| p.x).max().unwrap_or(self.width - 1);
+        let top = used.iter().map(|p| p.y).min().unwrap_or(0);
+        let bottom = used.iter().map(|p| p.y).max().unwrap_or(height - 1);
+
+        let mut data: Vec<Tile> = Vec::new();
+        for y in top..=bottom {
+            data.extend_from_slice(&self.data[y * self.width + left..=y * self.width + right]);
+        }
+        TileMatrix { width: right - left + 1, data }
+    }
+
+    // one of the 8 rotations & reflections. bit 0 flips x, bit 1 flips y & bit 2 swaps x with y, in that order.
+    fn transformed(&self, symmetry: usize) -> TileMatrix {
+        let height = self.data.len() / self.width;
+        let new_width = if symmetry & 4 != 0 { height } else { self.width };
+        let mut data = vec![Tile::Floor; self.data.len()];
+        for (i, tile) in self.data.iter().enumerate() {
+            let (mut x, mut y) = (i % self.width, i / self.width);
+            if symmetry & 1 != 0 { x = self.width - 1 - x; }
+            if symmetry & 2 != 0 { y = height - 1 - y; }
+            if symmetry & 4 != 0 { std::mem::swap(&mut x, &mut y); }
+            data[y * new_width + x] = *tile;
+        }
+        TileMatrix { width: new_width, data }
+    }
+
+    // moves the player to the first square, in reading order, that they can walk to without pushing.
+    fn with_normal_player(mut self) -> TileMatrix {
+        let start = match self.data.iter().position(|tile| matches!(tile, Tile::Player | Tile::PlayerGoal)) {
+            Some(start) => start,
+            None => return self,
+        };
+        let mut seen = vec![false; self.data.len()];
+        let mut stack = vec![start];
+        let mut first = start;
+        while let Some(current) = stack.pop() {
+            if seen[current] || matches!(self.data[current], Tile::Wall | Tile::Crate | Tile::CrateGoal) {
+                continue;
+            }
+            seen[current] = true;
+            first = first.min(current);
+            let (x, y) = (current % self.width, current / self.width);
+            if x > 0 { stack.push(current - 1); }
+            if x + 1 < self.width { stack.push(current + 1); }
+            if y > 0 { stack.push(current - self.width); }
+            if current + self.width < self.data.len() { stack.push(current + self.width); }
+        }
+
+        self.data[start] = if self.data[start] == Tile::PlayerGoal { Tile::Goal } else { Tile::Floor };
+        self.data[first] = if self.data[first] == Tile::Goal { Tile::PlayerGoal } else { Tile::Player };
+        self
+    }
+}
+
+// the puzzle in .sok format, with trailing floor removed from each row.
+impl fmt::Display for TileMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in self.data.chunks(self.width) {
+            let line: String = row.iter().map(|tile| tile.to_char()).collect();
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -296,6 +387,72 @@ impl BitMatrix {
     }
 }
 
+// Bitboard of crate positions, using the same layout as a TileMatrix. This is what the solver stores per node, so it
+// needs to be small, and cheap to hash & compare.
+#[derive(PartialEq, Eq, Hash)]
+pub struct CrateSet {
+    pub width: usize,
+    words: Vec<u64>,
+}
+impl Clone for CrateSet {
+    fn clone(&self) -> CrateSet {
+        CrateSet { width: self.width, words: self.words.clone() }
+    }
+
+    // reuses the words already allocated, for sets which are overwritten often.
+    fn clone_from(&mut self, source: &CrateSet) {
+        self.width = source.width;
+        self.words.clone_from(&source.words);
+    }
+}
+impl CrateSet {
+    pub fn new(width: usize, len: usize) -> CrateSet {
+        CrateSet {
+            width, words: vec![0; len.div_ceil(64)]
+        }
+    }
+    pub fn get(&self, p: Point2D) -> bool {
+        let i = p.y * self.width + p.x;
+        self.words[i / 64] & (1 << (i % 64)) != 0
+    }
+    pub fn set(&mut self, p: Point2D, val: bool) {
+        let i = p.y * self.width + p.x;
+        if val {
+            self.words[i / 64] |= 1 << (i % 64);
+        } else {
+            self.words[i / 64] &= !(1 << (i % 64));
+        }
+    }
+    pub fn move_crate(&mut self, from: Point2D, to: Point2D) {
+        self.set(from, false);
+        self.set(to, true);
+    }
+    // crate positions in index order.
+    pub fn iter(&self) -> impl Iterator<Item = Point2D> + '_ {
+        let width = self.width;
+        self.words.iter().enumerate().flat_map(|(w, word)| {
+            let mut bits = *word;
+            std::iter::from_fn(move || {
+                if bits == 0 {
+                    return None;
+                }
+                let bit = bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                Some(w * 64 + bit)
+            })
+        }).map(move |i| Point2D::new(i % width, i / width))
+    }
+}
+
+// A room holding every goal, which can only be entered through a single square.
+pub struct GoalRoom {
+    pub entrance: Point2D,
+    pub entry: Action,  // direction crates are pushed from the entrance into the room.
+    pub room: BitMatrix,
+    pub order: Vec<Point2D>,  // goals in the order they should be filled.
+    pub macros: Vec<Vec<(Point2D, Action)>>,  // (crate position, push) steps which fill order[i] from the entrance.
+}
+
 // Simple point struct
 #[derive(Copy, Clone, Hash, PartialEq, Eq)]
 pub struct Point2D {
@@ -316,12 +473,12 @@ impl Point2D {
             Action::PushDown => Point2D::new(self.x, self.y + 1),
             Action::PushLeft => Point2D::new(self.x - 1, self.y),
             Action::PushRight => Point2D::new(self.x + 1, self.y),
-            Action::NoMove => self.clone(),
+            Action::NoMove => *self,
         }
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Action {
     Up,
     Down,
@@ -352,6 +509,36 @@ impl Action {
         s
     }
 
+    // reads a LURD string, ignoring whitespace. Returns None on any other character.
+    pub fn from_string(s: &str) -> Option<Vec<Action>> {
+        s.chars().filter(|c| !c.is_whitespace()).map(|c| match c {
+            'u' => Some(Action::Up),
+            'd' => Some(Action::Down),
+            'l' => Some(Action::Left),
+            'r' => Some(Action::Right),
+            'U' => Some(Action::PushUp),
+            'D' => Some(Action::PushDown),
+            'L' => Some(Action::PushLeft),
+            'R' => Some(Action::PushRight),
+            _ => None,
+        }).collect()
+    }
+
+    pub fn is_push(&self) -> bool {
+        matches!(self, Action::PushUp | Action::PushDown | Action::PushLeft | Action::PushRight)
+    }
+
+    // the push in the same direction as a move.
+    pub fn to_push(&self) -> Action {
+        match self {
+            Action::Up => Action::PushUp,
+            Action::Down => Action::PushDown,
+            Action::Left => Action::PushLeft,
+            Action::Right => Action::PushRight,
+            _ => *self,
+        }
+    }
+
     // inverse of no move is no move.
     pub fn inverse(&self) -> Action {
         match self {
@@ -369,6 +556,7 @@ impl Action {
 }
 
 // This structure stores data about the analysis.
+#[derive(Default)]
 pub struct RunDat {
     pub nodes_checked: usize,
     pub nodes_generated: usize,
@@ -393,3 +581,80 @@ impl RunDat {
         println!("nodes skipped = {}", self.nodes_skipped);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // no symmetry of its own, so every transform is a different grid.
+    const LEVEL: &str = "\
+#######
+#  .  #
+# #$# #
+# $  ##
+#.@ ###
+#######
+";
+
+    #[test]
+    fn canonical_is_same_for_all_transforms() {
+        let level = TileMatrix::from_string(LEVEL);
+        let canonical = level.canonical().to_string();
+        let mut grids: Vec<String> = Vec::new();
+        for symmetry in 0..8 {
+            let transformed = level.transformed(symmetry);
+            assert_eq!(transformed.canonical().to_string(), canonical, "symmetry {}", symmetry);
+            assert_eq!(transformed.canonical_hash(), level.canonical_hash(), "symmetry {}", symmetry);
+            grids.push(transformed.to_string());
+        }
+        grids.sort();
+        grids.dedup();
+        assert_eq!(grids.len(), 8);
+    }
+
+    #[test]
+    fn canonical_ignores_floor_around_level() {
+        let padded: String = LEVEL.lines().map(|line| format!("  {}\n", line)).collect();
+        let padded = TileMatrix::from_string(&format!("\n{}\n", padded));
+        let level = TileMatrix::from_string(LEVEL);
+        assert_eq!(padded.canonical().to_string(), level.canonical().to_string());
+    }
+
+    #[test]
+    fn canonical_moves_player_to_first_square_of_region() {
+        let level = TileMatrix::from_string(LEVEL);
+        // the same region as LEVEL's player, reached without pushing.
+        let moved = TileMatrix::from_string(&LEVEL.replace("#.@ ###", "#.  ###").replace("#  .  #", "#  . @#"));
+        assert_eq!(moved.canonical().to_string(), level.canonical().to_string());
+        // standing on a goal in the region is the same as standing next to it.
+        let on_goal = TileMatrix::from_string(&LEVEL.replace("#.@ ###", "#+  ###"));
+        assert_eq!(on_goal.canonical().to_string(), level.canonical().to_string());
+
+        // the player is moved, but the first square of its region is kept in reading order.
+        let normal = level.clone().with_normal_player().to_string();
+        assert_eq!(normal.lines().nth(1), Some("#@ .  #"));
+        assert_eq!(normal.lines().nth(4), Some("#.  ###"));
+    }
+
+    #[test]
+    fn enclosed_levels() {
+        assert!(TileMatrix::from_string(LEVEL).is_enclosed());
+        // floor outside the walls doesn't matter, as nothing can get to it.
+        assert!(TileMatrix::from_string("  \n #####\n #@$.#\n #####\n").is_enclosed());
+        let open = TileMatrix::from_string_bare;
+        assert!(!open("#####\n@ $.#\n#####\n").is_enclosed());
+        assert!(!open("## ##\n#@$.#\n#####\n").is_enclosed());
+        assert!(!open("#####\n#@$. \n#####\n").is_enclosed());
+        // a crate or goal the player can't reach still has to be walled in.
+        assert!(!open("#####\n#@#.$\n#####\n").is_enclosed());
+    }
+
+    #[test]
+    fn canonical_keeps_player_regions_apart() {
+        // the crates split the floor, so the player can't walk between the two halves.
+        let left = TileMatrix::from_string("######\n#@$. #\n# $. #\n######\n");
+        let right = TileMatrix::from_string("######\n# $.@#\n# $. #\n######\n");
+        assert_ne!(left.canonical().to_string(), right.canonical().to_string());
+        assert_ne!(left.canonical_hash(), right.canonical_hash());
+    }
+}
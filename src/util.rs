@@ -1,33 +1,65 @@
-use std::collections::HashMap;
-use priority_queue::PriorityQueue;
-use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
 
-use crate::types::{Action, TileMatrix, Tile, Point2D, BitMatrix};
+use crate::types::{Action, TileMatrix, Tile, Point2D, BitMatrix, CrateSet, GoalRoom};
 
-// This module is for utility algorithms like floodfill, manhattan_dis, and a*.
+// This module is for utility algorithms like floodfill, manhattan_dis, and pathfinding.
 
 // ************************************************************************** //
 
-// effectively a recursive floodfill algorithm.
-pub fn find_walkable_spaces(map: &TileMatrix, current: Point2D, walk_map: &mut BitMatrix) {
-    let adjacent: Vec<Point2D> = vec![ 
-        current.from(Action::Left),
-        current.from(Action::Right),
-        current.from(Action::Up),
-        current.from(Action::Down),
-    ];
-    for point in adjacent {
-        if walk_map.get(point).unwrap() == false {
-            match map.get(point) {
-                Tile::Floor => {
-                    walk_map.set(point, true);
-                    find_walkable_spaces(map, point, walk_map);
-                },
-                Tile::Goal => {
-                    walk_map.set(point, true);
-                    find_walkable_spaces(map, point, walk_map);
-                },
-                _ => (),
+// Reusable scratch space for finding where the player can walk, which the solver keeps so that expanding a node
+// doesn't allocate. Each fill also finds the normalized player position & every push the player can make.
+pub struct Reachability {
+    stamps: Vec<u32>,  // a square is reachable when its stamp matches the current generation.
+    generation: u32,
+    stack: Vec<usize>,
+    pub player_norm: usize,  // smallest reachable index.
+    pub faces: Vec<(Point2D, Action)>,  // (crate position, push) for each crate face the player can push from.
+}
+impl Reachability {
+    pub fn new(len: usize) -> Reachability {
+        Reachability {
+            stamps: vec![0; len], generation: 0, stack: Vec::with_capacity(len), player_norm: 0, faces: Vec::new()
+        }
+    }
+
+    pub fn is_reachable(&self, p: Point2D, width: usize) -> bool {
+        self.stamps[p.y * width + p.x] == self.generation
+    }
+
+    // iterative floodfill from the player. static_map holds the walls, and crates block the player.
+    pub fn fill(&mut self, static_map: &TileMatrix, crates: &CrateSet, player: Point2D) {
+        if self.generation == u32::MAX {
+            self.stamps.iter_mut().for_each(|s| *s = 0);
+            self.generation = 0;
+        }
+        self.generation += 1;
+
+        let width = static_map.width;
+        let start = player.y * width + player.x;
+        self.stamps[start] = self.generation;
+        self.player_norm = start;
+        self.stack.clear();
+        self.stack.push(start);
+        while let Some(current) = self.stack.pop() {
+            // levels are checked to be enclosed when they're loaded, so neighbours can't leave the map.
+            for next in [current - 1, current + 1, current - width, current + width] {
+                if self.stamps[next] != self.generation && static_map.data[next] != Tile::Wall && 
+                   !crates.get(Point2D::new(next % width, next / width)) {
+                    self.stamps[next] = self.generation;
+                    self.player_norm = self.player_norm.min(next);
+                    self.stack.push(next);
+                }
+            }
+        }
+
+        self.faces.clear();
+        for crate_pos in crates.iter() {
+            for action in [Action::PushRight, Action::PushLeft, Action::PushDown, Action::PushUp] {
+                let crate_end = crate_pos.from(action);
+                if self.is_reachable(crate_pos.from(action.inverse()), width) && 
+                   static_map.get(crate_end) != Tile::Wall && !crates.get(crate_end) {
+                    self.faces.push((crate_pos, action));
+                }
             }
         }
     }
@@ -37,9 +69,19 @@ pub fn find_walkable_spaces(map: &TileMatrix, current: Point2D, walk_map: &mut B
 
 pub fn find_simple_deadlocks(map: &TileMatrix, goals: &Vec<Point2D>) -> BitMatrix {
     let mut bm = BitMatrix::new(map.width, map.data.len());
-    let mut new_map_data: Vec<Tile> = Vec::new();
+    let new_map = floor_map(map);
+    
+    // drag goal
+    for goal_pos in goals {
+        let mut cur_checked = BitMatrix::new(map.width, map.data.len());
+        recursive_pull(&new_map, &mut bm, &mut cur_checked, *goal_pos);
+    }
+    bm
+}
 
-    // remove all tiles but floor and wall.
+// copy of the map with all tiles but floor and wall removed.
+pub fn floor_map(map: &TileMatrix) -> TileMatrix {
+    let mut new_map_data: Vec<Tile> = Vec::new();
     for tile in &map.data {
         new_map_data.push(
             match tile {
@@ -52,48 +94,332 @@ pub fn find_simple_deadlocks(map: &TileMatrix, goals: &Vec<Point2D>) -> BitMatri
             }
         );
     }
-    let new_map = TileMatrix {
+    TileMatrix {
         width: map.width,
         data: new_map_data,
-    };
-    
-    // drag goal
-    for goal_pos in goals {
-        let mut cur_checked = BitMatrix::new(map.width, map.data.len());
-        recursive_pull(&new_map, &mut bm, &mut cur_checked, *goal_pos);
     }
-    bm
+}
+
+// A crate can be pulled in the direction of action when the player has room to step back, so both the square next
+// to the crate & the one past it must be floor.
+pub fn can_pull(map: &TileMatrix, crate_pos: Point2D, action: Action) -> bool {
+    let point = crate_pos.from(action);
+    map.get(point) == Tile::Floor && map.get(point.from(action)) == Tile::Floor
 }
 
 fn recursive_pull(map: &TileMatrix, bm: &mut BitMatrix, cur_checked: &mut BitMatrix, cur_pos: Point2D) {
     bm.set(cur_pos, true);
     cur_checked.set(cur_pos, true);
 
-    let adjacent: Vec<(Point2D, Action)> = vec![ 
-        (cur_pos.from(Action::Left), Action::Left),
-        (cur_pos.from(Action::Right), Action::Right),
-        (cur_pos.from(Action::Up), Action::Up),
-        (cur_pos.from(Action::Down), Action::Down),
-    ];
-    
     // check if adjacent boxes can be pulled
-    for (point, action) in adjacent {
-        if map.get(point) != Tile::Wall {
-            let next_point = point.from(action);
-            if map.get(next_point) != Tile::Wall {
-                if cur_checked.get(point).unwrap() == false && 
-                map.get(point) == Tile::Floor && 
-                map.get(next_point) == Tile::Floor {
-                    recursive_pull(map, bm, cur_checked, point);
+    for action in [Action::Left, Action::Right, Action::Up, Action::Down] {
+        let point = cur_pos.from(action);
+        if can_pull(map, cur_pos, action) && !cur_checked.get(point).unwrap() {
+            recursive_pull(map, bm, cur_checked, point);
+        }
+    }
+}
+
+// ************************************************************************** //
+
+// Finds one-wide corridors in the static map. The first matrix marks squares with walls above & below (horizontal
+// tunnels), and the second marks squares with walls left & right (vertical tunnels).
+pub fn find_tunnels(map: &TileMatrix) -> (BitMatrix, BitMatrix) {
+    let mut horizontal = BitMatrix::new(map.width, map.data.len());
+    let mut vertical = BitMatrix::new(map.width, map.data.len());
+    let height = map.data.len() / map.width;
+
+    // edge squares can never hold a crate, so they are skipped.
+    for y in 1..height.saturating_sub(1) {
+        for x in 1..map.width - 1 {
+            let pos = Point2D::new(x, y);
+            if map.get(pos) == Tile::Wall {
+                continue;
+            }
+            if map.get(pos.from(Action::Up)) == Tile::Wall && map.get(pos.from(Action::Down)) == Tile::Wall {
+                horizontal.set(pos, true);
+            }
+            if map.get(pos.from(Action::Left)) == Tile::Wall && map.get(pos.from(Action::Right)) == Tile::Wall {
+                vertical.set(pos, true);
+            }
+        }
+    }
+    (horizontal, vertical)
+}
+
+// ************************************************************************** //
+
+// Looks for the smallest room which holds every goal and can only be entered through a single square. The goals are
+// then given a packing order by working backwards from a full room: the goal filled last is the closest one which a
+// crate can still be pushed to from the entrance while all the other remaining goals are filled.
+pub fn find_goal_room(map: &TileMatrix, goals: &[Point2D]) -> Option<GoalRoom> {
+    if goals.is_empty() {
+        return None;
+    }
+    let height = map.data.len() / map.width;
+    let is_inner = |p: Point2D| p.x > 0 && p.y > 0 && p.x < map.width - 1 && p.y < height - 1;
+
+    let mut best: Option<(Point2D, Action, BitMatrix, usize)> = None;
+    for y in 1..height.saturating_sub(1) {
+        for x in 1..map.width - 1 {
+            let entrance = Point2D::new(x, y);
+            if map.get(entrance) == Tile::Wall || goals.contains(&entrance) {
+                continue;
+            }
+            let (room, size) = match fill_room(map, goals[0], entrance) {
+                Some(v) => v,
+                None => continue,
+            };
+            if !goals.iter().all(|g| room.get(*g).unwrap()) {
+                continue;
+            }
+
+            // the entrance must lead into the room from exactly one side, with space for the player behind it.
+            let entries: Vec<Action> = [Action::PushUp, Action::PushDown, Action::PushLeft, Action::PushRight].iter()
+                .filter(|a| room.get(entrance.from(**a)).unwrap()).cloned().collect();
+            if entries.len() != 1 {
+                continue;
+            }
+            let outside = entrance.from(entries[0].inverse());
+            if map.get(outside) == Tile::Wall || !is_inner(outside) {
+                continue;
+            }
+
+            let is_better = match &best {
+                Some((_, _, _, best_size)) => size < *best_size,
+                None => true,
+            };
+            if is_better {
+                best = Some((entrance, entries[0], room, size));
+            }
+        }
+    }
+    let (entrance, entry, room, _) = best?;
+
+    let mut remaining: Vec<Point2D> = goals.to_vec();
+    let mut order: Vec<Point2D> = Vec::new();
+    let mut macros: Vec<Vec<(Point2D, Action)>> = Vec::new();
+    while !remaining.is_empty() {
+        remaining.sort_by_key(|g| manhattan_distance(*g, entrance));
+        let mut found: Option<(usize, Vec<(Point2D, Action)>)> = None;
+        for (i, goal) in remaining.iter().enumerate() {
+            let filled: Vec<Point2D> = remaining.iter().filter(|g| *g != goal).cloned().collect();
+            if let Some(pushes) = room_push_path(map, &room, &filled, entrance, entry, *goal) {
+                found = Some((i, pushes));
+                break;
+            }
+        }
+        let (i, pushes) = found?;  // no packing order could be found.
+        order.push(remaining.remove(i));
+        macros.push(pushes);
+    }
+    order.reverse();
+    macros.reverse();
+
+    Some(GoalRoom { entrance, entry, room, order, macros })
+}
+
+// floodfills the squares connected to start without passing through the entrance. Rooms containing crates or the
+// player are rejected.
+fn fill_room(map: &TileMatrix, start: Point2D, entrance: Point2D) -> Option<(BitMatrix, usize)> {
+    let mut room = BitMatrix::new(map.width, map.data.len());
+    let mut stack: Vec<Point2D> = vec![start];
+    let mut size = 1;
+    room.set(start, true);
+    while let Some(current) = stack.pop() {
+        match map.get(current) {
+            Tile::Crate | Tile::CrateGoal | Tile::Player | Tile::PlayerGoal => return None,
+            _ => (),
+        }
+        for action in &[Action::Up, Action::Down, Action::Left, Action::Right] {
+            let point = current.from(*action);
+            if point != entrance && map.get(point) != Tile::Wall && !room.get(point).unwrap() {
+                room.set(point, true);
+                stack.push(point);
+                size += 1;
+            }
+        }
+    }
+    Some((room, size))
+}
+
+// BFS over pushes of a single crate from the entrance to the given goal, with the filled goals acting as walls.
+// The player starts just outside the entrance.
+fn room_push_path(map: &TileMatrix, room: &BitMatrix, filled: &[Point2D], entrance: Point2D, 
+                  entry: Action, goal: Point2D) -> Option<Vec<(Point2D, Action)>> {
+    let outside = entrance.from(entry.inverse());
+    let is_open = |p: Point2D| (p == entrance || p == outside || room.get(p).unwrap()) && !filled.contains(&p);
+
+    let start = (entrance, outside);
+    let mut came_from: HashMap<(Point2D, Point2D), ((Point2D, Point2D), Action)> = HashMap::new();
+    let mut seen: HashSet<(Point2D, Point2D)> = HashSet::new();
+    let mut queue: VecDeque<(Point2D, Point2D)> = VecDeque::new();
+    seen.insert(start);
+    queue.push_back(start);
+
+    while let Some((crate_pos, player)) = queue.pop_front() {
+        if crate_pos == goal {
+            let mut pushes: Vec<(Point2D, Action)> = Vec::new();
+            let mut current = (crate_pos, player);
+            while let Some((previous, action)) = came_from.get(&current) {
+                pushes.push((previous.0, *action));
+                current = *previous;
+            }
+            pushes.reverse();
+            return Some(pushes);
+        }
+
+        // squares the player can walk to without moving the crate.
+        let mut reach = BitMatrix::new(map.width, map.data.len());
+        let mut stack: Vec<Point2D> = vec![player];
+        reach.set(player, true);
+        while let Some(current) = stack.pop() {
+            for action in &[Action::Up, Action::Down, Action::Left, Action::Right] {
+                let point = current.from(*action);
+                if point != crate_pos && is_open(point) && !reach.get(point).unwrap() {
+                    reach.set(point, true);
+                    stack.push(point);
                 }
             }
         }
+
+        for action in &[Action::PushUp, Action::PushDown, Action::PushLeft, Action::PushRight] {
+            let push_from = crate_pos.from(action.inverse());
+            let crate_end = crate_pos.from(*action);
+            if !reach.get(push_from).unwrap() || !room.get(crate_end).unwrap() || filled.contains(&crate_end) {
+                continue;
+            }
+            let next = (crate_end, crate_pos);
+            if seen.insert(next) {
+                came_from.insert(next, ((crate_pos, player), *action));
+                queue.push_back(next);
+            }
+        }
     }
-    
+    None
 }
 
 // ************************************************************************** //
 
+// Plays actions on the puzzle, returning the (crates, player) state before the first push & after every push. Walks
+// may be written as either case, but a push must be uppercase.
+pub fn replay_pushes(puzzle: &TileMatrix, actions: &[Action]) -> Result<Vec<(CrateSet, Point2D)>, String> {
+    let mut crates = CrateSet::new(puzzle.width, puzzle.data.len());
+    let mut player: Option<Point2D> = None;
+    for (i, tile) in puzzle.data.iter().enumerate() {
+        let pos = Point2D::new(i % puzzle.width, i / puzzle.width);
+        match tile {
+            Tile::Crate | Tile::CrateGoal => crates.set(pos, true),
+            Tile::Player | Tile::PlayerGoal => player = Some(pos),
+            _ => (),
+        }
+    }
+    let mut player = player.ok_or("puzzle has no player")?;
+
+    let mut states = vec![(crates.clone(), player)];
+    for (n, action) in actions.iter().enumerate() {
+        let next = player.from(*action);
+        if puzzle.get(next) == Tile::Wall {
+            return Err(format!("move {} walks into a wall", n + 1));
+        }
+        if crates.get(next) {
+            let crate_end = next.from(*action);
+            if !action.is_push() {
+                return Err(format!("move {} walks into a crate", n + 1));
+            } else if puzzle.get(crate_end) == Tile::Wall || crates.get(crate_end) {
+                return Err(format!("move {} pushes a crate into a wall or crate", n + 1));
+            }
+            crates.move_crate(next, crate_end);
+            player = next;
+            states.push((crates.clone(), player));
+        } else if action.is_push() {
+            return Err(format!("move {} is a push without a crate", n + 1));
+        } else {
+            player = next;
+        }
+    }
+    Ok(states)
+}
+
+// Checks that the actions solve the puzzle, returning the (pushes, moves) of the solution.
+pub fn verify_solution(puzzle: &TileMatrix, actions: &[Action]) -> Result<(usize, usize), String> {
+    let states = replay_pushes(puzzle, actions)?;
+    let (crates, _) = states.last().unwrap();
+    let solved = crates.iter().all(|c| matches!(puzzle.get(c), Tile::Goal | Tile::CrateGoal | Tile::PlayerGoal));
+    if !solved {
+        return Err("not every crate ends on a goal".to_string());
+    }
+    Ok((states.len() - 1, actions.len()))
+}
+
+// Rebuilds a solution from its (crate position, push) steps, taking the shortest walk before each push.
+pub fn walk_pushes(walks: &mut PathCache, static_map: &TileMatrix, mut crates: CrateSet, mut player: Point2D, 
+                   pushes: &[(Point2D, Action)]) -> Vec<Action> {
+    let mut actions: Vec<Action> = Vec::new();
+    for &(crate_start, action) in pushes {
+        let push_from = crate_start.from(action.inverse());
+        actions.append(&mut walks.walk(static_map, &crates, player, push_from));
+        actions.push(action);
+        crates.move_crate(crate_start, crate_start.from(action));
+        player = crate_start;
+    }
+    actions
+}
+
+// Splits a solution into the pushes made on each crate, with crates numbered in the order they appear in the level.
+// Solutions which only differ in walking, or in the order of pushes on different crates, give the same paths.
+pub fn crate_push_paths(puzzle: &TileMatrix, actions: &[Action]) -> Vec<Vec<Action>> {
+    let mut crate_ids: Vec<Option<usize>> = vec![None; puzzle.data.len()];
+    let mut paths: Vec<Vec<Action>> = Vec::new();
+    let mut player = Point2D::new(0, 0);
+    for (i, tile) in puzzle.data.iter().enumerate() {
+        match tile {
+            Tile::Crate | Tile::CrateGoal => {
+                crate_ids[i] = Some(paths.len());
+                paths.push(Vec::new());
+            },
+            Tile::Player | Tile::PlayerGoal => player = Point2D::new(i % puzzle.width, i / puzzle.width),
+            _ => (),
+        }
+    }
+
+    for action in actions {
+        player = player.from(*action);
+        if action.is_push() {
+            let from = player.y * puzzle.width + player.x;
+            let to = player.from(*action);
+            if let Some(id) = crate_ids[from].take() {
+                crate_ids[to.y * puzzle.width + to.x] = Some(id);
+                paths[id].push(*action);
+            }
+        }
+    }
+    paths
+}
+
+// Squares the player could ever walk on, found by a floodfill from the player that passes through crates. Anything
+// left out is cut off by walls alone.
+pub fn find_walkable_spaces(map: &TileMatrix) -> BitMatrix {
+    let mut walkable = BitMatrix::new(map.width, map.data.len());
+    let mut stack: Vec<usize> = map.data.iter()
+        .position(|tile| matches!(tile, Tile::Player | Tile::PlayerGoal))
+        .into_iter().collect();
+    while let Some(current) = stack.pop() {
+        let p = Point2D::new(current % map.width, current / map.width);
+        if map.data[current] == Tile::Wall || walkable.get(p).unwrap() {
+            continue;
+        }
+        walkable.set(p, true);
+
+        // the edge of the map is outside, so the floodfill stops there.
+        if p.x > 0 { stack.push(current - 1); }
+        if p.x + 1 < map.width { stack.push(current + 1); }
+        if p.y > 0 { stack.push(current - map.width); }
+        if current + map.width < map.data.len() { stack.push(current + map.width); }
+    }
+    walkable
+}
+
 pub fn manhattan_distance(p1: Point2D, p2: Point2D) -> usize {
     let mut val: usize = 0;
     if p1.x < p2.x {
@@ -111,83 +437,117 @@ pub fn manhattan_distance(p1: Point2D, p2: Point2D) -> usize {
 
 // ************************************************************************** //
 
-// the heuristic function used is simple manhattan distance
-pub fn astar_pathfind(puzzle_map: &TileMatrix, push_action: Action, 
-                      start_point: Point2D, goal_point: Point2D) -> Vec<Action> {
-    let mut path = a_star(puzzle_map, start_point, goal_point, manhattan_distance);
-    path.push(push_action);
-    path
-}
-
-fn reconstruct_path(came_from: &HashMap<Point2D, Action>, goal: Point2D) -> Vec<Action> {
-    // this with-capacity is just a random-sucky estimate.
-    let mut total_path: Vec<Action> = Vec::with_capacity(came_from.len()/4);
-    let mut current = goal;
-    while came_from.contains_key(&current) {
-        let action = came_from[&current];
-        total_path.push(action);
-        current = current.from(action.inverse());
-    }
-    total_path.reverse();
-    total_path
-}
-
-// A* finds a path from start to goal.
-// h is the heuristic function. h(n) estimates the cost to reach goal from node n.
-fn a_star(puzzle_map: &TileMatrix, start: Point2D, goal: Point2D, h: fn(Point2D, Point2D) -> usize) -> Vec<Action> {
-    // The set of discovered nodes that may need to be (re-)expanded.
-    // Initially, only the start node is known.
-    // This is usually implemented as a min-heap or priority queue rather than a hash-set.
-    let mut open_queue = PriorityQueue::new();
-    open_queue.push( start, Reverse( 0 + h(start, goal) ) );
-
-    // For node n, came_from[n] is the node immediately preceding it on the cheapest path from start
-    // to n currently known.
-    let mut came_from: HashMap<Point2D, Action> = HashMap::new();
-
-    // For node n, g_score[n] is the cost of the cheapest path from start to n currently known.
-    let mut g_score: HashMap<Point2D, usize> = HashMap::new(); // map with default value of Infinity
-    g_score.insert(start, 0);
-
-    while !open_queue.is_empty() {
-        let (current, _priority) = open_queue.pop().unwrap();
-        if current == goal {
-            return reconstruct_path(&came_from, goal);
-        }
-
-        let neighbors = vec![
-            Action::Up, Action::Down, Action::Left, Action::Right
-        ];
-        for action in neighbors {
-            let neighbor_point = current.from(action);
-
-            // prune nodes which are not walkable. -> player nodes are considered walkable.
-            match puzzle_map.get(neighbor_point) {
-                Tile::Wall => continue,
-                Tile::Crate => continue,
-                Tile::CrateGoal => continue,
-                _ => (),
-            }
-
-            // tentative_g_score is the distance from start to the neighbor through current
-            let tentative_g_score = g_score[&current] + 1;
-            if let Some(g) = g_score.get(&neighbor_point) {
-                if tentative_g_score >= *g {
-                    continue;
-                }
+// Finds the player's shortest walks between squares with a breadth first search, since every step costs one. Walks
+// are cached for the whole solve by (crate layout, start, goal), because collected solutions share most of their
+// states. The search itself runs over flat arrays which are reused between calls.
+pub struct PathCache {
+    walks: HashMap<(CrateSet, Point2D, Point2D), Vec<Action>>,
+    stamps: Vec<u32>,  // a square has been visited when its stamp matches the current generation.
+    generation: u32,
+    came_from: Vec<Action>,  // the step which first reached each visited square.
+    queue: VecDeque<usize>,
+}
+impl PathCache {
+    pub fn new(len: usize) -> PathCache {
+        PathCache {
+            walks: HashMap::new(), stamps: vec![0; len], generation: 0, came_from: vec![Action::NoMove; len], 
+            queue: VecDeque::with_capacity(len)
+        }
+    }
+
+    // the steps to walk from start to goal, where static_map holds the walls, and crates block the player.
+    pub fn walk(&mut self, static_map: &TileMatrix, crates: &CrateSet, start: Point2D, goal: Point2D) -> Vec<Action> {
+        let key = (crates.clone(), start, goal);
+        if let Some(walk) = self.walks.get(&key) {
+            return walk.clone();
+        }
+
+        let walk = self.bfs(static_map, crates, start, goal);
+        self.walks.insert(key, walk.clone());
+        walk
+    }
+
+    fn bfs(&mut self, static_map: &TileMatrix, crates: &CrateSet, start: Point2D, goal: Point2D) -> Vec<Action> {
+        if self.generation == u32::MAX {
+            self.stamps.iter_mut().for_each(|s| *s = 0);
+            self.generation = 0;
+        }
+        self.generation += 1;
+
+        let width = static_map.width;
+        let goal_index = goal.y * width + goal.x;
+        self.stamps[start.y * width + start.x] = self.generation;
+        self.queue.clear();
+        self.queue.push_back(start.y * width + start.x);
+        while let Some(current) = self.queue.pop_front() {
+            if current == goal_index {
+                break;
             }
 
-            // This path to neighbor is better than any previous one. (or none exist) Record it!
-            came_from.insert(neighbor_point, action);
-            g_score.insert(neighbor_point, tentative_g_score);
-            let f_score = g_score.get(&neighbor_point).unwrap() + h(neighbor_point, goal);
-            if open_queue.change_priority( &neighbor_point, Reverse(f_score) ).is_none() {
-                open_queue.push( neighbor_point, Reverse(f_score) );
+            let current_point = Point2D::new(current % width, current / width);
+            for action in [Action::Up, Action::Down, Action::Left, Action::Right] {
+                let next = current_point.from(action);
+                let next_index = next.y * width + next.x;
+                if self.stamps[next_index] != self.generation && static_map.get(next) != Tile::Wall && !crates.get(next) {
+                    self.stamps[next_index] = self.generation;
+                    self.came_from[next_index] = action;
+                    self.queue.push_back(next_index);
+                }
             }
         }
+
+        // the goal should always be reachable because of the validity of the flood fill algorithm
+        if self.stamps[goal_index] != self.generation {
+            return Vec::new();
+        }
+
+        let mut path: Vec<Action> = Vec::new();
+        let mut current = goal;
+        while current != start {
+            let action = self.came_from[current.y * width + current.x];
+            path.push(action);
+            current = current.from(action.inverse());
+        }
+        path.reverse();
+        path
     }
-    // We should never get here because of the validity of the flood fill algorithm
-    return Vec::new(); 
 }
 
 // ************************************************************************** //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the left crate takes one push & the right crate two, & neither gets in the other's way.
+    const LEVEL: &str = "\
+#########
+#.$  $ .#
+#   @   #
+#########
+";
+
+    fn paths(level: &TileMatrix, lurd: &str) -> Vec<String> {
+        let actions = Action::from_string(lurd).unwrap();
+        if let Err(reason) = verify_solution(level, &actions) {
+            panic!("{} is not a solution: {}", lurd, reason);
+        }
+        crate_push_paths(level, &actions).iter().map(Action::to_string).collect()
+    }
+
+    #[test]
+    fn push_paths_ignore_order_of_independent_crates() {
+        let level = TileMatrix::from_string(LEVEL);
+        let right_first = paths(&level, "uRRlllL");
+        let left_first = paths(&level, "ulLrrRR");
+        assert_eq!(right_first, vec!["L", "RR"]);
+        assert_eq!(left_first, right_first);
+    }
+
+    #[test]
+    fn push_paths_ignore_walking() {
+        let level = TileMatrix::from_string(LEVEL);
+        assert_eq!(paths(&level, "luLrrRR"), paths(&level, "ulLrrRR"));
+        assert_eq!(paths(&level, "uRRdllluL"), paths(&level, "uRRlllL"));
+    }
+}